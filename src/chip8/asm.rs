@@ -0,0 +1,904 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+/**
+ * Address where the programs are loaded.
+ */
+const START: usize = 0x200;
+
+/**
+ * Maximum number of macro expansions, to stop recursive macros.
+ */
+const MAX_EXPANSIONS: usize = 0x10000;
+
+/**
+ * @brief Error found while assembling a program.
+ */
+#[derive(Debug)]
+pub struct AsmError {
+    pub line: usize,     // Line of the source, starting from 1
+    pub message: String, // What went wrong
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl error::Error for AsmError {}
+
+#[derive(Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+/**
+ * Structured control flow waiting for its end.
+ * Addresses are the ones of the jumps to patch.
+ */
+enum Block {
+    If(usize),
+    Else(usize),
+    Loop { start: usize, breaks: Vec<usize> },
+}
+
+/**
+ * Reference to a label which was not defined yet.
+ */
+struct Fixup {
+    at: usize, // Address of the instruction to patch
+    label: String,
+    long: bool, // If true, the address is the 16 bits word at `at`
+    line: usize,
+}
+
+/**
+ * @brief Condition of an `if` or a `while`.
+ *
+ * Holds the instructions skipping the next one
+ * when the condition is false, and when it is true.
+ */
+struct Cond {
+    skip_false: u16,
+    skip_true: u16,
+}
+
+struct Assembler {
+    tokens: Vec<Token>, // Remaining tokens, in reverse order
+    line: usize,
+    rom: Vec<u8>,
+    pc: usize,
+    labels: HashMap<String, usize>,
+    consts: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    expansions: usize,
+}
+
+/**
+ * @brief Assemble a program written with the Octo syntax.
+ *
+ * Supported are labels (`: name`), `:const`, `:alias`, `:macro`,
+ * `:org`, `:byte`, structured `if ... then`, `if ... begin ... else ... end`,
+ * `loop ... while ... again`, raw bytes (such as `0b00111100` for
+ * sprites), and the CHIP-8, SUPER-CHIP and XO-CHIP statements.
+ * Return the bytes to load at 0x200.
+ */
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut tokens = tokenize(source);
+    tokens.reverse();
+    let mut asm = Assembler {
+        tokens,
+        line: 1,
+        rom: Vec::new(),
+        pc: START,
+        labels: HashMap::new(),
+        consts: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+        expansions: 0,
+    };
+
+    while let Some(tok) = asm.tokens.pop() {
+        asm.line = tok.line;
+        asm.statement(&tok.text)?;
+    }
+    asm.finish()
+}
+
+/**
+ * Split the source on whitespaces, dropping the `#` comments.
+ */
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (n, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or("");
+        for word in code.split_whitespace() {
+            tokens.push(Token {
+                text: word.to_string(),
+                line: n + 1,
+            });
+        }
+    }
+    tokens
+}
+
+/**
+ * Parse a decimal, hexadecimal (0x) or binary (0b) number.
+ */
+fn parse_number(s: &str) -> Option<i64> {
+    let (neg, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+    let value = if let Some(hex) = s.strip_prefix("0x").or(s.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = s.strip_prefix("0b").or(s.strip_prefix("0B")) {
+        i64::from_str_radix(bin, 2).ok()?
+    } else {
+        s.parse::<i64>().ok()?
+    };
+    Some(if neg { -value } else { value })
+}
+
+impl Assembler {
+    fn error<T>(&self, message: String) -> Result<T, AsmError> {
+        Err(AsmError {
+            line: self.line,
+            message,
+        })
+    }
+
+    fn next(&mut self) -> Result<String, AsmError> {
+        match self.tokens.pop() {
+            Some(tok) => {
+                self.line = tok.line;
+                Ok(tok.text)
+            }
+            None => self.error("Unexpected end of file".to_string()),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.last().map(|t| t.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AsmError> {
+        let tok = self.next()?;
+        if tok != text {
+            return self.error(format!("Expected '{}', found '{}'", text, tok));
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, at: usize, byte: u8) {
+        let idx = at - START;
+        if idx >= self.rom.len() {
+            self.rom.resize(idx + 1, 0);
+        }
+        self.rom[idx] = byte;
+    }
+
+    fn byte(&mut self, byte: u8) {
+        self.write(self.pc, byte);
+        self.pc += 1;
+    }
+
+    fn emit(&mut self, word: u16) {
+        self.byte((word >> 8) as u8);
+        self.byte(word as u8);
+    }
+
+    fn as_register(&self, tok: &str) -> Option<u8> {
+        if let Some(&r) = self.aliases.get(tok) {
+            return Some(r);
+        }
+        let mut chars = tok.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v' | 'V'), Some(c), None) => c.to_digit(16).map(|d| d as u8),
+            _ => None,
+        }
+    }
+
+    fn register(&mut self) -> Result<u16, AsmError> {
+        let tok = self.next()?;
+        match self.as_register(&tok) {
+            Some(r) => Ok(r as u16),
+            None => self.error(format!("Expected a register, found '{}'", tok)),
+        }
+    }
+
+    fn as_value(&self, tok: &str) -> Option<i64> {
+        parse_number(tok).or_else(|| self.consts.get(tok).copied())
+    }
+
+    fn value(&mut self, tok: &str, max: i64) -> Result<u16, AsmError> {
+        match self.as_value(tok) {
+            Some(v) if v >= -(max + 1) / 2 && v <= max => Ok((v & max) as u16),
+            Some(v) => self.error(format!("Value {} does not fit in {:#x}", v, max)),
+            None => self.error(format!("Expected a value, found '{}'", tok)),
+        }
+    }
+
+    fn byte_value(&mut self) -> Result<u16, AsmError> {
+        let tok = self.next()?;
+        self.value(&tok, 0xFF)
+    }
+
+    fn nibble_value(&mut self) -> Result<u16, AsmError> {
+        let tok = self.next()?;
+        self.value(&tok, 0xF)
+    }
+
+    /**
+     * Emit an instruction taking an address, which can be a
+     * label defined later. Long addresses are in the word following `op`.
+     */
+    fn emit_addr(&mut self, op: u16, long: bool) -> Result<(), AsmError> {
+        let tok = self.next()?;
+        let max = if long { 0xFFFF } else { 0xFFF };
+        let addr = match self.labels.get(&tok) {
+            Some(&a) => a as i64,
+            None => match self.as_value(&tok) {
+                Some(v) => v,
+                None => {
+                    self.fixups.push(Fixup {
+                        at: self.pc,
+                        label: tok,
+                        long,
+                        line: self.line,
+                    });
+                    0
+                }
+            },
+        };
+        if !(0..=max).contains(&addr) {
+            return self.error(format!("Address {:#x} out of range", addr));
+        }
+        if long {
+            self.emit(op);
+            self.emit(addr as u16);
+        } else {
+            self.emit(op | addr as u16);
+        }
+        Ok(())
+    }
+
+    /**
+     * Replace the jump at `at` by a jump to `target`.
+     */
+    fn patch(&mut self, at: usize, target: usize) -> Result<(), AsmError> {
+        if target > 0xFFF {
+            return self.error(format!("Jump target {:#x} out of range", target));
+        }
+        let word = 0x1000 | target as u16;
+        self.write(at, (word >> 8) as u8);
+        self.write(at + 1, word as u8);
+        Ok(())
+    }
+
+    fn check_name(&self, name: &str) -> Result<(), AsmError> {
+        if parse_number(name).is_some() || self.as_register(name).is_some() {
+            return self.error(format!("Invalid name '{}'", name));
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, tok: &str) -> Result<(), AsmError> {
+        match tok {
+            ":" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                if self.labels.insert(name.clone(), self.pc).is_some() {
+                    return self.error(format!("Label '{}' is already defined", name));
+                }
+            }
+            ":const" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let tok = self.next()?;
+                let value = match self.labels.get(&tok) {
+                    Some(&a) => a as i64,
+                    None => self.value(&tok, 0xFFFF)? as i64,
+                };
+                self.consts.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let reg = self.register()?;
+                self.aliases.insert(name, reg as u8);
+            }
+            ":org" => {
+                let tok = self.next()?;
+                let addr = self.value(&tok, 0xFFFF)? as usize;
+                if addr < START {
+                    return self.error(format!("Address {:#x} is before {:#x}", addr, START));
+                }
+                self.pc = addr;
+            }
+            ":byte" => {
+                let b = self.byte_value()?;
+                self.byte(b as u8);
+            }
+            ":macro" => self.define_macro()?,
+            "clear" => self.emit(0x00E0),
+            "return" | ";" => self.emit(0x00EE),
+            "exit" => self.emit(0x00FD),
+            "lores" => self.emit(0x00FE),
+            "hires" => self.emit(0x00FF),
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-left" => self.emit(0x00FC),
+            "scroll-down" => {
+                let n = self.nibble_value()?;
+                self.emit(0x00C0 | n);
+            }
+            "scroll-up" => {
+                let n = self.nibble_value()?;
+                self.emit(0x00D0 | n);
+            }
+            "audio" => self.emit(0xF002),
+            "plane" => {
+                let n = self.nibble_value()?;
+                self.emit(0xF001 | n << 8);
+            }
+            "jump" => self.emit_addr(0x1000, false)?,
+            "jump0" => self.emit_addr(0xB000, false)?,
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    let op = if tok == "save" { 0x5002 } else { 0x5003 };
+                    self.emit(op | x << 8 | y << 4);
+                } else {
+                    let op = if tok == "save" { 0xF055 } else { 0xF065 };
+                    self.emit(op | x << 8);
+                }
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(0xF075 | x << 8);
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(0xF085 | x << 8);
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble_value()?;
+                self.emit(0xD000 | x << 8 | y << 4 | n);
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(0xF033 | x << 8);
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let op = match tok {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.emit(op | x << 8);
+            }
+            "i" => self.index()?,
+            "if" => {
+                let cond = self.condition()?;
+                match self.next()?.as_str() {
+                    "then" => self.emit(cond.skip_false),
+                    "begin" => {
+                        self.emit(cond.skip_true);
+                        self.blocks.push(Block::If(self.pc));
+                        self.emit(0x1000);
+                    }
+                    t => return self.error(format!("Expected 'then' or 'begin', found '{}'", t)),
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::If(at)) => {
+                    self.blocks.push(Block::Else(self.pc));
+                    self.emit(0x1000);
+                    self.patch(at, self.pc)?;
+                }
+                _ => return self.error("'else' without 'if ... begin'".to_string()),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If(at)) | Some(Block::Else(at)) => self.patch(at, self.pc)?,
+                _ => return self.error("'end' without 'if ... begin'".to_string()),
+            },
+            "loop" => self.blocks.push(Block::Loop {
+                start: self.pc,
+                breaks: Vec::new(),
+            }),
+            "while" => {
+                let cond = self.condition()?;
+                self.emit(cond.skip_true);
+                let at = self.pc;
+                self.emit(0x1000);
+                match self.blocks.iter_mut().rev().find_map(|b| match b {
+                    Block::Loop { breaks, .. } => Some(breaks),
+                    _ => None,
+                }) {
+                    Some(breaks) => breaks.push(at),
+                    None => return self.error("'while' outside of a loop".to_string()),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, breaks }) => {
+                    let at = self.pc;
+                    self.emit(0x1000);
+                    self.patch(at, start)?;
+                    for at in breaks {
+                        self.patch(at, self.pc)?;
+                    }
+                }
+                _ => return self.error("'again' without 'loop'".to_string()),
+            },
+            _ => {
+                if let Some(x) = self.as_register(tok) {
+                    self.arithmetic(x as u16)?;
+                } else if self.as_value(tok).is_some() {
+                    let b = self.value(tok, 0xFF)?;
+                    self.byte(b as u8);
+                } else if self.macros.contains_key(tok) {
+                    self.expand(tok)?;
+                } else if tok.starts_with(':') {
+                    return self.error(format!("Unknown directive '{}'", tok));
+                } else {
+                    // A bare label is a call to a subroutine
+                    self.tokens.push(Token {
+                        text: tok.to_string(),
+                        line: self.line,
+                    });
+                    self.emit_addr(0x2000, false)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Statements starting with `i`.
+     */
+    fn index(&mut self) -> Result<(), AsmError> {
+        match self.next()?.as_str() {
+            "+=" => {
+                let x = self.register()?;
+                self.emit(0xF01E | x << 8);
+            }
+            ":=" => match self.peek() {
+                Some("hex") => {
+                    self.next()?;
+                    let x = self.register()?;
+                    self.emit(0xF029 | x << 8);
+                }
+                Some("bighex") => {
+                    self.next()?;
+                    let x = self.register()?;
+                    self.emit(0xF030 | x << 8);
+                }
+                Some("long") => {
+                    self.next()?;
+                    self.emit_addr(0xF000, true)?;
+                }
+                _ => self.emit_addr(0xA000, false)?,
+            },
+            t => return self.error(format!("Unknown operator 'i {}'", t)),
+        }
+        Ok(())
+    }
+
+    /**
+     * Statements starting with a register.
+     */
+    fn arithmetic(&mut self, x: u16) -> Result<(), AsmError> {
+        let op = self.next()?;
+        let rhs = self.next()?;
+        let y = self.as_register(&rhs).map(|r| r as u16);
+        let word = match (op.as_str(), y) {
+            (":=", Some(y)) => 0x8000 | y << 4,
+            ("|=", Some(y)) => 0x8001 | y << 4,
+            ("&=", Some(y)) => 0x8002 | y << 4,
+            ("^=", Some(y)) => 0x8003 | y << 4,
+            ("+=", Some(y)) => 0x8004 | y << 4,
+            ("-=", Some(y)) => 0x8005 | y << 4,
+            (">>=", Some(y)) => 0x8006 | y << 4,
+            ("=-", Some(y)) => 0x8007 | y << 4,
+            ("<<=", Some(y)) => 0x800E | y << 4,
+            (":=", None) => match rhs.as_str() {
+                "delay" => 0xF007,
+                "key" => 0xF00A,
+                "random" => 0xC000 | self.byte_value()?,
+                _ => 0x6000 | self.value(&rhs, 0xFF)?,
+            },
+            ("+=", None) => 0x7000 | self.value(&rhs, 0xFF)?,
+            ("-=", None) => 0x7000 | (self.value(&rhs, 0xFF)?.wrapping_neg() & 0xFF),
+            _ => return self.error(format!("Unknown operator '{} {}'", op, rhs)),
+        };
+        self.emit(word | x << 8);
+        Ok(())
+    }
+
+    /**
+     * Parse a condition, emitting the instructions needed to test it.
+     *
+     * Comparisons other than equality are computed in VF, by
+     * subtracting the operands: VF = 1 if a >= b.
+     */
+    fn condition(&mut self) -> Result<Cond, AsmError> {
+        let x = self.register()?;
+        let op = self.next()?;
+        let cond = match op.as_str() {
+            "key" | "-key" => {
+                let (up, down) = (0xE0A1 | x << 8, 0xE09E | x << 8);
+                if op == "key" {
+                    Cond {
+                        skip_false: up,
+                        skip_true: down,
+                    }
+                } else {
+                    Cond {
+                        skip_false: down,
+                        skip_true: up,
+                    }
+                }
+            }
+            "==" | "!=" => {
+                let rhs = self.next()?;
+                let (ne, eq) = match self.as_register(&rhs) {
+                    Some(y) => (
+                        0x9000 | x << 8 | (y as u16) << 4,
+                        0x5000 | x << 8 | (y as u16) << 4,
+                    ),
+                    None => {
+                        let nn = self.value(&rhs, 0xFF)?;
+                        (0x4000 | x << 8 | nn, 0x3000 | x << 8 | nn)
+                    }
+                };
+                if op == "==" {
+                    Cond {
+                        skip_false: ne,
+                        skip_true: eq,
+                    }
+                } else {
+                    Cond {
+                        skip_false: eq,
+                        skip_true: ne,
+                    }
+                }
+            }
+            "<" | ">" | "<=" | ">=" => {
+                let rhs = self.next()?;
+                let y = self.as_register(&rhs).map(|r| r as u16);
+                if x == 0xF || y == Some(0xF) {
+                    return self.error("VF can not be compared, it holds the result".to_string());
+                }
+                // Test a >= b, x being a for '<' and '>='
+                let x_first = op == "<" || op == ">=";
+                match y {
+                    Some(y) => {
+                        let (a, b) = if x_first { (x, y) } else { (y, x) };
+                        self.emit(0x8F00 | a << 4);
+                        self.emit(0x8F05 | b << 4);
+                    }
+                    None => {
+                        let nn = self.value(&rhs, 0xFF)?;
+                        if x_first {
+                            // VF = x - nn
+                            self.emit(0x6F00 | nn);
+                            self.emit(0x8F07 | x << 4);
+                        } else {
+                            // VF = nn - x
+                            self.emit(0x6F00 | nn);
+                            self.emit(0x8F05 | x << 4);
+                        }
+                    }
+                }
+                // The condition holds if VF == k
+                let k = (op == ">=" || op == "<=") as u16;
+                Cond {
+                    skip_false: 0x4F00 | k,
+                    skip_true: 0x3F00 | k,
+                }
+            }
+            _ => return self.error(format!("Unknown condition '{}'", op)),
+        };
+        Ok(cond)
+    }
+
+    fn define_macro(&mut self) -> Result<(), AsmError> {
+        let name = self.next()?;
+        self.check_name(&name)?;
+        let mut args = Vec::new();
+        loop {
+            let tok = self.next()?;
+            if tok == "{" {
+                break;
+            }
+            args.push(tok);
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let tok = self.tokens.pop();
+            let tok = match tok {
+                Some(tok) => tok,
+                None => return self.error(format!("Unterminated macro '{}'", name)),
+            };
+            match tok.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            body.push(tok);
+        }
+        self.macros.insert(name, Macro { args, body });
+        Ok(())
+    }
+
+    /**
+     * Replace a macro call by its body, where arguments are substituted.
+     */
+    fn expand(&mut self, name: &str) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return self.error(format!("Too many expansions of macro '{}'", name));
+        }
+        let nargs = self.macros[name].args.len();
+        let mut values = Vec::with_capacity(nargs);
+        for _ in 0..nargs {
+            values.push(self.next()?);
+        }
+
+        let line = self.line;
+        let mac = &self.macros[name];
+        let expanded: Vec<Token> = mac
+            .body
+            .iter()
+            .map(|tok| {
+                let text = match mac.args.iter().position(|a| *a == tok.text) {
+                    Some(i) => values[i].clone(),
+                    None => tok.text.clone(),
+                };
+                Token { text, line }
+            })
+            .collect();
+        self.tokens.extend(expanded.into_iter().rev());
+        Ok(())
+    }
+
+    /**
+     * Resolve the forward references, and return the program.
+     */
+    fn finish(mut self) -> Result<Vec<u8>, AsmError> {
+        if !self.blocks.is_empty() {
+            let what = match self.blocks.last() {
+                Some(Block::Loop { .. }) => "'loop' without 'again'",
+                _ => "'if ... begin' without 'end'",
+            };
+            return self.error(what.to_string());
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let addr = match self.labels.get(&fixup.label) {
+                Some(&a) => a,
+                None => return self.error(format!("Undefined label '{}'", fixup.label)),
+            };
+            if fixup.long {
+                self.write(fixup.at + 2, (addr >> 8) as u8);
+                self.write(fixup.at + 3, addr as u8);
+            } else {
+                if addr > 0xFFF {
+                    return self.error(format!("Address {:#x} out of range", addr));
+                }
+                let hi = self.rom[fixup.at - START];
+                self.write(fixup.at, hi | (addr >> 8) as u8);
+                self.write(fixup.at + 1, addr as u8);
+            }
+        }
+        Ok(self.rom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assemble;
+    use crate::chip8::disasm::disassemble;
+    use crate::chip8::HeadlessChip8;
+
+    /**
+     * Each instruction form, its encoding and its disassembly.
+     */
+    const FORMS: &[(&str, &[u8], &str)] = &[
+        ("clear", &[0x00, 0xE0], "CLS"),
+        ("return", &[0x00, 0xEE], "RET"),
+        ("scroll-down 3", &[0x00, 0xC3], "SCD 3"),
+        ("scroll-up 2", &[0x00, 0xD2], "SCU 2"),
+        ("scroll-right", &[0x00, 0xFB], "SCR"),
+        ("scroll-left", &[0x00, 0xFC], "SCL"),
+        ("exit", &[0x00, 0xFD], "EXIT"),
+        ("lores", &[0x00, 0xFE], "LOW"),
+        ("hires", &[0x00, 0xFF], "HIGH"),
+        ("jump 0x345", &[0x13, 0x45], "JP 0x345"),
+        ("if v3 != 0x12 then", &[0x33, 0x12], "SE V3, 0x12"),
+        ("if v3 == 0x12 then", &[0x43, 0x12], "SNE V3, 0x12"),
+        ("if v3 != v4 then", &[0x53, 0x40], "SE V3, V4"),
+        ("save v2 - v5", &[0x52, 0x52], "SAVE V2 - V5"),
+        ("load v2 - v5", &[0x52, 0x53], "LOAD V2 - V5"),
+        ("v3 := 0x12", &[0x63, 0x12], "LD V3, 0x12"),
+        ("v3 += 0x12", &[0x73, 0x12], "ADD V3, 0x12"),
+        ("v3 -= 1", &[0x73, 0xFF], "ADD V3, 0xFF"),
+        ("v3 := v4", &[0x83, 0x40], "LD V3, V4"),
+        ("v3 |= v4", &[0x83, 0x41], "OR V3, V4"),
+        ("v3 &= v4", &[0x83, 0x42], "AND V3, V4"),
+        ("v3 ^= v4", &[0x83, 0x43], "XOR V3, V4"),
+        ("v3 += v4", &[0x83, 0x44], "ADD V3, V4"),
+        ("v3 -= v4", &[0x83, 0x45], "SUB V3, V4"),
+        ("v3 >>= v4", &[0x83, 0x46], "SHR V3, V4"),
+        ("v3 =- v4", &[0x83, 0x47], "SUBN V3, V4"),
+        ("v3 <<= v4", &[0x83, 0x4E], "SHL V3, V4"),
+        ("if v3 == v4 then", &[0x93, 0x40], "SNE V3, V4"),
+        ("i := 0x345", &[0xA3, 0x45], "LD I, 0x345"),
+        ("jump0 0x345", &[0xB3, 0x45], "JP V0, 0x345"),
+        ("v3 := random 0x0f", &[0xC3, 0x0F], "RND V3, 0x0F"),
+        ("sprite v3 v4 5", &[0xD3, 0x45], "DRW V3, V4, 5"),
+        ("if v3 -key then", &[0xE3, 0x9E], "SKP V3"),
+        ("if v3 key then", &[0xE3, 0xA1], "SKNP V3"),
+        (
+            "i := long 0x1234",
+            &[0xF0, 0x00, 0x12, 0x34],
+            "LD I, 0x1234",
+        ),
+        ("plane 3", &[0xF3, 0x01], "PLANE 3"),
+        ("audio", &[0xF0, 0x02], "AUDIO"),
+        ("v3 := delay", &[0xF3, 0x07], "LD V3, DT"),
+        ("v3 := key", &[0xF3, 0x0A], "LD V3, K"),
+        ("delay := v3", &[0xF3, 0x15], "LD DT, V3"),
+        ("buzzer := v3", &[0xF3, 0x18], "LD ST, V3"),
+        ("i += v3", &[0xF3, 0x1E], "ADD I, V3"),
+        ("i := hex v3", &[0xF3, 0x29], "LD F, V3"),
+        ("i := bighex v3", &[0xF3, 0x30], "LD HF, V3"),
+        ("bcd v3", &[0xF3, 0x33], "LD B, V3"),
+        ("pitch := v3", &[0xF3, 0x3A], "PITCH V3"),
+        ("save v3", &[0xF3, 0x55], "LD [I], V3"),
+        ("load v3", &[0xF3, 0x65], "LD V3, [I]"),
+        ("saveflags v3", &[0xF3, 0x75], "LD R, V3"),
+        ("loadflags v3", &[0xF3, 0x85], "LD V3, R"),
+    ];
+
+    #[test]
+    fn instruction_forms() {
+        for (source, bytes, _) in FORMS {
+            assert_eq!(assemble(source).unwrap(), *bytes, "{}", source);
+        }
+    }
+
+    #[test]
+    fn disassembly_round_trip() {
+        let source: Vec<&str> = FORMS.iter().map(|f| f.0).collect();
+        let rom = assemble(&source.join("\n")).unwrap();
+        let lines = disassemble(&rom, 0x200);
+        let texts: Vec<&str> = lines.iter().map(|l| l.text.as_str()).collect();
+        let expected: Vec<&str> = FORMS.iter().map(|f| f.2).collect();
+        assert_eq!(texts, expected);
+    }
+
+    /**
+     * Run a program until it loops on itself, and return V0.
+     */
+    fn run(source: &str) -> u8 {
+        let rom = assemble(source).unwrap();
+        let mut chip = HeadlessChip8::new_headless();
+        chip.load_program(&rom).unwrap();
+        chip.run_frame(100).unwrap();
+        chip.registers()[0]
+    }
+
+    #[test]
+    fn comparisons() {
+        let holds = |op: &str, a: u8, b: u8| match op {
+            "==" => a == b,
+            "!=" => a != b,
+            "<" => a < b,
+            ">" => a > b,
+            "<=" => a <= b,
+            _ => a >= b,
+        };
+        for op in ["==", "!=", "<", ">", "<=", ">="] {
+            for (a, b) in [(1, 2), (2, 2), (3, 2), (0, 255)] {
+                let expected = holds(op, a, b);
+                for rhs in ["v2".to_string(), b.to_string()] {
+                    let then = format!(
+                        "v1 := {} v2 := {} v0 := 0 if v1 {} {} then v0 := 1 loop again",
+                        a, b, op, rhs
+                    );
+                    assert_eq!(run(&then), expected as u8, "{}", then);
+
+                    let begin = format!(
+                        "v1 := {} v2 := {} if v1 {} {} begin v0 := 1 else v0 := 2 end loop again",
+                        a, b, op, rhs
+                    );
+                    assert_eq!(run(&begin), 2 - expected as u8, "{}", begin);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn loops() {
+        let source = "v0 := 0 loop v0 += 3 while v0 != 12 again loop again";
+        assert_eq!(run(source), 12);
+    }
+
+    #[test]
+    fn labels_and_forward_references() {
+        let source = "
+            : main
+                jump later
+            : sub
+                return
+            : later
+                sub
+                i := data
+                i := long data
+            : data
+                0x12
+        ";
+        let expected = [
+            0x12, 0x04, 0x00, 0xEE, 0x22, 0x02, 0xA2, 0x0C, 0xF0, 0x00, 0x02, 0x0C, 0x12,
+        ];
+        assert_eq!(assemble(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn consts_aliases_and_macros() {
+        let source = "
+            :const SPEED 5
+            :alias px v3
+            :macro twice X { X X }
+            px := SPEED
+            twice clear
+            sprite px px SPEED
+            0b00111100
+        ";
+        let expected = [0x63, 0x05, 0x00, 0xE0, 0x00, 0xE0, 0xD3, 0x35, 0x3C];
+        assert_eq!(assemble(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn error_lines() {
+        let cases = [
+            ("clear\n\nv1 := 0x100\n", 3),
+            ("clear\njump nowhere\nclear\n", 2),
+            (": a\nclear\n: a\n", 3),
+            ("clear # comment\nelse\n", 2),
+            ("loop\nclear\n:frobnicate\n", 3),
+            ("if vf < v1 then\n", 1),
+        ];
+        for (source, line) in cases {
+            let err = assemble(source).unwrap_err();
+            assert_eq!(err.line, line, "{:?}: {}", source, err);
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use super::Audio;
+
 use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
 use sdl2::AudioSubsystem;
 
@@ -5,17 +7,34 @@ const BUZZER_VOLUME: f32 = 0.02;
 
 /**
  * Structure for generating the sound.
+ *
+ * Plays a square wave, or the XO-CHIP pattern once one is set.
  */
-struct SquareWave {
+pub struct SquareWave {
     phase_inc: f32,
     phase: f32,
     volume: f32,
+    freq: f32,                 // Sampling frequency of the device
+    pattern: Option<[u8; 16]>, // XO-CHIP 1-bit pattern
+    pattern_inc: f32,
+    pattern_phase: f32,
 }
 
 impl AudioCallback for SquareWave {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
+        // Play the pattern bit by bit, most significant bit first
+        if let Some(pattern) = &self.pattern {
+            for x in out.iter_mut() {
+                let bit = (self.pattern_phase * 128.) as usize % 128;
+                let high = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+                *x = if high { self.volume } else { -self.volume };
+                self.pattern_phase = (self.pattern_phase + self.pattern_inc) % 1.0;
+            }
+            return;
+        }
+
         // Generate a square wave
         for x in out.iter_mut() {
             *x = if self.phase <= 0.5 {
@@ -52,21 +71,32 @@ impl Buzzer {
                     phase_inc: 440.0 / spec.freq as f32,
                     phase: 0.5,
                     volume: BUZZER_VOLUME,
+                    freq: spec.freq as f32,
+                    pattern: None,
+                    pattern_inc: 0.,
+                    pattern_phase: 0.,
                 })?,
         })
     }
+}
 
-    /**
-     * @brief Start buzzing
-     */
-    pub fn start(&self) {
+impl Audio for Buzzer {
+    fn start(&mut self) {
         self.device.resume();
     }
 
-    /**
-     * @brief Stop buzzing
-     */
-    pub fn stop(&self) {
+    fn stop(&mut self) {
         self.device.pause();
     }
+
+    fn set_pattern(&mut self, pattern: &[u8; 16], rate: f32) {
+        // The callback runs on the audio thread, it has to be locked
+        let mut wave = self.device.lock();
+        wave.pattern_inc = rate / (128. * wave.freq);
+        wave.pattern = Some(*pattern);
+    }
+
+    fn clear_pattern(&mut self) {
+        self.device.lock().pattern = None;
+    }
 }
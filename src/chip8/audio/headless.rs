@@ -0,0 +1,55 @@
+use super::Audio;
+
+/**
+ * @brief Audio backend without any sound device.
+ *
+ * Only remembers if the buzzer would be playing, and what.
+ */
+pub struct HeadlessAudio {
+    playing: bool,
+    pattern: Option<[u8; 16]>, // XO-CHIP pattern played instead of the tone
+}
+
+impl HeadlessAudio {
+    pub fn new() -> Self {
+        HeadlessAudio {
+            playing: false,
+            pattern: None,
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /**
+     * The XO-CHIP pattern played, None when playing the tone.
+     */
+    pub fn pattern(&self) -> Option<&[u8; 16]> {
+        self.pattern.as_ref()
+    }
+}
+
+impl Default for HeadlessAudio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Audio for HeadlessAudio {
+    fn start(&mut self) {
+        self.playing = true;
+    }
+
+    fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8; 16], _rate: f32) {
+        self.pattern = Some(*pattern);
+    }
+
+    fn clear_pattern(&mut self) {
+        self.pattern = None;
+    }
+}
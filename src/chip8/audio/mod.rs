@@ -0,0 +1,37 @@
+// Exported modules
+pub mod buzzer;
+pub mod headless;
+
+// Avoid verbosity
+pub use buzzer::Buzzer;
+pub use headless::HeadlessAudio;
+
+/**
+ * @brief Sound output used by the Chip8 for its sound timer.
+ *
+ * The Chip8 only knows about a single tone, so an audio
+ * backend only has to be able to start and stop it.
+ * The XO-CHIP can replace this tone by a 1-bit pattern.
+ */
+pub trait Audio {
+    /**
+     * @brief Start buzzing
+     */
+    fn start(&mut self);
+
+    /**
+     * @brief Stop buzzing
+     */
+    fn stop(&mut self);
+
+    /**
+     * @brief Play the 128 bits `pattern` in a loop instead of the tone,
+     * at `rate` bits per second.
+     */
+    fn set_pattern(&mut self, _pattern: &[u8; 16], _rate: f32) {}
+
+    /**
+     * @brief Play the tone again instead of the pattern.
+     */
+    fn clear_pattern(&mut self) {}
+}
@@ -1,4 +1,7 @@
-use super::{Buzzer, Chip8, ChipCfg, ChipInst, SDLDisplay, DEFAULT_FONT};
+use super::{Audio, Buzzer, Chip8, Chip8Error, ChipCfg, ChipInst, Debugger, Display, ErrorPolicy};
+use super::{HeadlessAudio, HeadlessChip8, HeadlessDisplay, HeadlessInput};
+use super::{Input, KeyWait, Profile, Renderer, Rng};
+use super::{SDLChip8, SDLDisplay, SDLInput, BIG_FONT, DEFAULT_FONT};
 use std::fs::File;
 use std::io;
 use std::io::Read;
@@ -6,59 +9,239 @@ use std::io::Read;
 use sdl2::video;
 use sdl2::AudioSubsystem;
 
-#[allow(clippy::unused_io_amount)]
-
-impl Chip8 {
+impl SDLChip8 {
     /**
      * @brief Create a Chip8 emulator with SDL.
+     *
+     * Failures to open the audio device or the renderer
+     * are reported as backend errors.
      */
-    pub fn new_sdl(
-        win: video::Window,
-        audio: &AudioSubsystem,
-    ) -> Result<Self, sdl2::IntegerOrSdlError> {
+    pub fn new_sdl(win: video::Window, audio: &AudioSubsystem) -> Result<Self, Chip8Error> {
         // Create the buzzer, linked to SDL's audio subsystem.
-        let buzzer = Buzzer::new(audio).unwrap();
+        let buzzer = Buzzer::new(audio).map_err(Chip8Error::Backend)?;
+        let display = SDLDisplay::new(win).map_err(|e| Chip8Error::Backend(e.to_string()))?;
+
+        Ok(Chip8::new(display, buzzer, SDLInput::new()))
+    }
+}
 
-        Ok(Chip8 {
+impl HeadlessChip8 {
+    /**
+     * @brief Create a Chip8 emulator without any window, sound or input device.
+     */
+    pub fn new_headless() -> Self {
+        Chip8::new(
+            HeadlessDisplay::new(),
+            HeadlessAudio::new(),
+            HeadlessInput::new(),
+        )
+    }
+}
+
+impl<R: Renderer, A: Audio, I: Input> Chip8<R, A, I> {
+    /**
+     * @brief Create a Chip8 emulator from its peripherals.
+     */
+    pub fn new(renderer: R, audio: A, input: I) -> Self {
+        Chip8 {
             i: 0,
             pc: 0x200,
             dt: 0,
             st: 0,
             sp: 0,
             v: [0; 16],
+            rpl: [0; 16],
             stack: [0; 32],
-            mem: [0; 4096],
-            disp: SDLDisplay::new(win)?,
-            audio: buzzer,
+            mem: vec![0; ChipCfg::default().mem_size],
+            program: Vec::new(),
+            pattern: None,
+            pitch: 64,
+            rng: Rng::from_entropy(),
+            disp: Display::new(),
+            renderer,
+            audio,
+            input,
             config: Default::default(),
+            debugger: Debugger::new(),
+            tracer: None,
             exit: false,
-        })
+            vblank_wait: false,
+            dirty: false,
+            vip_budget: 0,
+            key_wait: KeyWait::Idle,
+        }
+    }
+
+    pub fn config(&self) -> &ChipCfg {
+        &self.config
+    }
+
+    /**
+     * Change the retro-compatibility options.
+     * Changing `font_start` only applies to the next loaded font.
+     */
+    pub fn config_mut(&mut self) -> &mut ChipCfg {
+        &mut self.config
+    }
+
+    /**
+     * Apply the quirks of a platform, keeping the other options.
+     */
+    pub fn set_profile(&mut self, profile: Profile) {
+        self.config = ChipCfg {
+            font_start: self.config.font_start,
+            big_font_start: self.config.big_font_start,
+            on_error: self.config.on_error,
+            ..ChipCfg::from_profile(profile)
+        };
+        self.mem.resize(self.config.mem_size, 0);
+    }
+
+    /**
+     * Seed the random numbers of CXNN, to make runs reproducible.
+     */
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    /**
+     * Choose what happens when an instruction fails.
+     */
+    pub fn set_error_policy(&mut self, policy: ErrorPolicy) {
+        self.config.on_error = policy;
+    }
+
+    pub fn input(&self) -> &I {
+        &self.input
+    }
+
+    /**
+     * Give access to the keypad, so the frontend can update it.
+     */
+    pub fn input_mut(&mut self) -> &mut I {
+        &mut self.input
+    }
+
+    pub fn renderer(&self) -> &R {
+        &self.renderer
+    }
+
+    pub fn renderer_mut(&mut self) -> &mut R {
+        &mut self.renderer
+    }
+
+    pub fn audio(&self) -> &A {
+        &self.audio
+    }
+
+    /**
+     * The content of the screen.
+     */
+    pub fn framebuffer(&self) -> &Display {
+        &self.disp
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    pub fn index(&self) -> u16 {
+        self.i
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    /**
+     * Return addresses of the subroutines being executed,
+     * the innermost last.
+     */
+    pub fn call_stack(&self) -> &[u16] {
+        &self.stack[1..=self.sp as usize]
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.dt
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.st
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.mem
     }
+
     /**
      * Load a program from the bytes of a file
      */
-    pub fn load_file(&mut self, path: &str) -> Result<(), io::Error> {
+    pub fn load_file(&mut self, path: &str) -> Result<(), Chip8Error> {
         // Load the file
         let mut f: File = File::open(path)?;
-
-        // Start writing at address 0x200 (512)
-        // Because 0x0 - 0x1FF is kept for internal use
-        let addr = 0x200;
-        f.read(&mut self.mem[addr..])?;
-        Ok(())
+        let mut prgm = Vec::new();
+        f.read_to_end(&mut prgm)?;
+        self.load_program(&prgm)
     }
 
     /**
      * Load a program from a byte array
      */
-    pub fn load_program(&mut self, arr: &[u8]) {
+    pub fn load_program(&mut self, arr: &[u8]) -> Result<(), Chip8Error> {
+        // Start writing at address 0x200 (512)
+        // Because 0x0 - 0x1FF is kept for internal use
         let startprgm: &mut [u8] = &mut self.mem[0x200..];
 
         if startprgm.len() < arr.len() {
-            panic!("Program is to large !");
+            return Err(Chip8Error::RomTooLarge {
+                size: arr.len(),
+                max: startprgm.len(),
+            });
         }
 
-        startprgm.clone_from_slice(arr);
+        startprgm[..arr.len()].copy_from_slice(arr);
+        self.program = arr.to_vec();
+        Ok(())
+    }
+
+    /**
+     * @brief Restart the last loaded program, as on power up.
+     *
+     * Registers, stack, timers, memory, screen and audio pattern are
+     * cleared, then the default font and the program are loaded again.
+     * The configuration, the debugger and the tracer are kept.
+     */
+    pub fn reset(&mut self) -> Result<(), Chip8Error> {
+        self.i = 0;
+        self.pc = 0x200;
+        self.dt = 0;
+        self.st = 0;
+        self.sp = 0;
+        self.v = [0; 16];
+        self.rpl = [0; 16];
+        self.stack = [0; 32];
+        self.mem = vec![0; self.config.mem_size];
+        self.pattern = None;
+        self.pitch = 64;
+        self.disp = Display::new();
+        self.exit = false;
+        self.vblank_wait = false;
+        self.key_wait = KeyWait::Idle;
+        self.vip_budget = 0;
+        self.input.clear_events();
+
+        self.load_default_font();
+        let program = std::mem::take(&mut self.program);
+        self.load_program(&program)?;
+
+        self.refresh_pattern();
+        self.refresh_buzzer();
+        self.dirty = false;
+        self.present()
     }
 
     pub fn load_font(&mut self, path: &str) -> Result<(), io::Error> {
@@ -79,14 +262,100 @@ impl Chip8 {
         for (i, item) in DEFAULT_FONT.iter().enumerate() {
             self.mem[offset + i] = *item;
         }
+
+        // Load the hardcoded big font
+        let offset = self.config.big_font_start as usize;
+        for (i, item) in BIG_FONT.iter().enumerate() {
+            self.mem[offset + i] = *item;
+        }
     }
 
-    pub fn fetch(&mut self) -> ChipInst {
-        let b1 = self.mem[self.pc as usize];
-        let b2 = self.mem[(self.pc + 1) as usize];
-        self.pc += 2;
+    pub fn fetch(&mut self) -> Result<ChipInst, Chip8Error> {
+        let addr = self.pc;
+        self.pc = self.pc.wrapping_add(2);
+
+        if addr as usize + 1 >= self.mem.len() {
+            return Err(Chip8Error::OutOfBounds {
+                addr,
+                access: addr as usize + 1,
+            });
+        }
+
+        let b1 = self.mem[addr as usize];
+        let b2 = self.mem[addr as usize + 1];
         let w: u16 = ((b1 as u16) << 8) | (b2 as u16);
-        ChipInst::new(w)
+        Ok(ChipInst::new(w))
+    }
+
+    /**
+     * Fetch and execute a single instruction.
+     */
+    pub fn step(&mut self) -> Result<(), Chip8Error> {
+        let addr = self.pc;
+        let v = self.v;
+        match self.fetch() {
+            Ok(inst) => {
+                if self.config.vip_timing {
+                    self.vip_budget -= self.vip_cost(&inst) as i32;
+                }
+                let res = self.execute(&inst);
+                let traced = match self.tracer {
+                    Some(_) => self.trace(addr, &inst, &v),
+                    None => Ok(()),
+                };
+                res.and(traced)
+            }
+            Err(e) => self.handle_error(e),
+        }
+    }
+
+    /**
+     * @brief Run one 60Hz frame.
+     *
+     * Execute `cycles` instructions, or with the VIP timing as many
+     * as fit in a frame of the COSMAC VIP, then update the timers
+     * and the buzzer once, and present the screen if it changed.
+     * If an instruction fails, or waits for the display,
+     * the rest of the frame is dropped.
+     * The frames are paced by the frontend, see `FrameScheduler`.
+     */
+    pub fn run_frame(&mut self, cycles: u32) -> Result<(), Chip8Error> {
+        self.begin_frame();
+        let mut executed = 0;
+        let mut res = Ok(());
+        while res.is_ok() && !self.frame_over(executed, cycles) {
+            res = self.step();
+            executed += 1;
+        }
+        self.update_timers();
+        self.refresh_buzzer();
+        let shown = self.present_frame();
+        res.and(shown)
+    }
+
+    /**
+     * Present the screen, if it changed since it was last presented.
+     */
+    pub fn present_frame(&mut self) -> Result<(), Chip8Error> {
+        if !self.dirty {
+            return Ok(());
+        }
+        self.dirty = false;
+        self.present()
+    }
+
+    /**
+     * @brief Apply the error policy to a failed instruction.
+     */
+    pub(super) fn handle_error(&mut self, e: Chip8Error) -> Result<(), Chip8Error> {
+        match self.config.on_error {
+            ErrorPolicy::Halt => {
+                self.exit = true;
+                Err(e)
+            }
+            ErrorPolicy::Skip => Err(e),
+            ErrorPolicy::Ignore => Ok(()),
+        }
     }
 
     pub fn has_exited(&self) -> bool {
@@ -103,6 +372,22 @@ impl Chip8 {
         }
     }
 
+    /**
+     * @brief Send the XO-CHIP audio pattern to the audio backend.
+     *
+     * The pattern is played at 4000 * 2 ^ ((pitch - 64) / 48) bits per second.
+     * Programs which never set a pattern play the tone.
+     */
+    pub(super) fn refresh_pattern(&mut self) {
+        match &self.pattern {
+            Some(pattern) => {
+                let rate = 4000. * 2f32.powf((self.pitch as f32 - 64.) / 48.);
+                self.audio.set_pattern(pattern, rate);
+            }
+            None => self.audio.clear_pattern(),
+        }
+    }
+
     pub fn refresh_buzzer(&mut self) {
         if self.st == 0 {
             self.audio.stop()
@@ -116,9 +401,40 @@ impl Default for ChipCfg {
     fn default() -> Self {
         ChipCfg {
             font_start: 0x050,
+            big_font_start: 0x0A0,
+            mem_size: 0x1000,
             off_jump_legacy: false,
             reg_save_legacy: false,
             index_add_carry: false,
+            logic_vf_reset: false,
+            shift_legacy: false,
+            sprite_wrap: false,
+            display_wait: false,
+            key_release_wait: true,
+            vip_timing: false,
+            on_error: ErrorPolicy::Halt,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::HeadlessChip8;
+
+    #[test]
+    fn reset_restores_the_tone() {
+        let mut chip = HeadlessChip8::new_headless();
+        chip.config_mut().vip_timing = true;
+        // I = pattern; audio; loop; pattern
+        let mut rom = vec![0xA2, 0x06, 0xF0, 0x02, 0x12, 0x04];
+        rom.extend_from_slice(&[0xF0; 16]);
+        chip.load_program(&rom).unwrap();
+        chip.run_frame(10).unwrap();
+        assert_eq!(chip.audio().pattern(), Some(&[0xF0; 16]));
+
+        chip.reset().unwrap();
+        assert!(chip.audio().pattern().is_none());
+        assert_eq!(chip.pc(), 0x200);
+        assert_eq!(chip.vip_budget, 0);
+    }
+}
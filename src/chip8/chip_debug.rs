@@ -1,14 +1,14 @@
-use super::Chip8;
+use super::{Audio, Chip8, Input, Renderer};
 use std::cmp::min;
 use std::fmt;
 
-impl fmt::Debug for Chip8 {
+impl<R: Renderer, A: Audio, I: Input> fmt::Debug for Chip8<R, A, I> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // Build a string for showing a part of the memory
         // From index to index + 10
 
         let low = self.pc as usize;
-        let up = min(1 + (self.pc as usize) + 10, 0x1000);
+        let up = min(1 + (self.pc as usize) + 10, self.mem.len());
 
         let mut mem_str = String::new();
         for i in low..up {
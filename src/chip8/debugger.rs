@@ -0,0 +1,602 @@
+use super::{Audio, Chip8, Chip8Error, ChipInst, Input, Renderer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+/**
+ * @brief A value of the chip that conditions and watchpoints can look at.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    V(u8),    // One of the V0 - VF registers
+    I,        // The index register
+    Dt,       // The delay timer
+    St,       // The sound timer
+    Sp,       // The stack pointer
+    Mem(u16), // The byte at an address
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/**
+ * @brief Condition of a breakpoint, such as `V3 == 0x10`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub lhs: Operand,
+    pub op: CmpOp,
+    pub rhs: u16,
+}
+
+/**
+ * @brief Kind of memory access which triggers a watchpoint.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Any,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Watchpoint {
+    Memory { range: Range<u16>, access: Access }, // Accesses to these bytes
+    Register(Operand),                            // Changes of a value
+}
+
+/**
+ * @brief Why the debugger stopped the chip.
+ */
+#[derive(Debug)]
+pub enum StopReason {
+    Step,            // The step, step over, step out or run to cursor is done
+    Breakpoint(u16), // A breakpoint was reached, before executing its instruction
+    Watchpoint {
+        index: usize, // Index of the watchpoint, in `Debugger::watchpoints`
+        addr: u16,    // Address of the instruction which triggered it
+    },
+    Exited,            // The program exited
+    Error(Chip8Error), // An instruction failed
+}
+
+/**
+ * Where the chip runs to before stopping.
+ */
+#[derive(Debug, Clone, Copy)]
+enum Target {
+    Steps(u32),                 // A number of instructions
+    Return { pc: u16, sp: u8 }, // The instruction after a call
+    Out(u8),                    // The return from the current subroutine
+    Addr(u16),                  // An address, as long as no breakpoint is hit
+    Forever,                    // The next breakpoint or watchpoint
+}
+
+/**
+ * @brief Breakpoints, watchpoints and run state of a chip.
+ *
+ * The chip is paused by default. Commands such as `resume`
+ * or `step_over` tell where to run, then each call to
+ * `Chip8::debug_frame` executes a frame, until the chip stops.
+ */
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: BTreeMap<u16, Option<Condition>>,
+    watchpoints: Vec<Watchpoint>,
+    target: Option<Target>,
+    resumed: bool, // Ignore the breakpoint of the first instruction
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /**
+     * Stop before executing the instruction at `addr`,
+     * if `cond` holds.
+     */
+    pub fn add_breakpoint(&mut self, addr: u16, cond: Option<Condition>) {
+        self.breakpoints.insert(addr, cond);
+    }
+
+    /**
+     * Return true if there was a breakpoint at `addr`.
+     */
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr).is_some()
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (u16, Option<&Condition>)> {
+        self.breakpoints.iter().map(|(a, c)| (*a, c.as_ref()))
+    }
+
+    pub fn has_breakpoint(&self, addr: u16) -> bool {
+        self.breakpoints.contains_key(&addr)
+    }
+
+    /**
+     * Add a watchpoint, and return its index.
+     */
+    pub fn add_watchpoint(&mut self, watch: Watchpoint) -> usize {
+        self.watchpoints.push(watch);
+        self.watchpoints.len() - 1
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        if index < self.watchpoints.len() {
+            Some(self.watchpoints.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /**
+     * Return true if the chip is running towards a target.
+     */
+    pub fn is_running(&self) -> bool {
+        self.target.is_some()
+    }
+
+    /**
+     * Stop the chip before the next instruction.
+     */
+    pub fn pause(&mut self) {
+        self.target = None;
+    }
+
+    /**
+     * Run until a breakpoint or a watchpoint is hit.
+     */
+    pub fn resume(&mut self) {
+        self.run(Target::Forever);
+    }
+
+    /**
+     * Execute `n` instructions.
+     */
+    pub fn step(&mut self, n: u32) {
+        self.run(Target::Steps(n));
+    }
+
+    /**
+     * Run until the chip reaches `addr`.
+     */
+    pub fn run_to(&mut self, addr: u16) {
+        self.run(Target::Addr(addr));
+    }
+
+    fn run(&mut self, target: Target) {
+        self.target = Some(target);
+        self.resumed = true;
+    }
+}
+
+impl<R: Renderer, A: Audio, I: Input> Chip8<R, A, I> {
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /**
+     * Execute the next instruction, stepping over subroutine calls.
+     */
+    pub fn step_over(&mut self) {
+        let call = self.word_at(self.pc).is_some_and(|w| w & 0xF000 == 0x2000);
+        let target = if call {
+            Target::Return {
+                pc: self.pc.wrapping_add(2),
+                sp: self.sp,
+            }
+        } else {
+            Target::Steps(1)
+        };
+        self.debugger.run(target);
+    }
+
+    /**
+     * Run until the current subroutine returns.
+     * Return false, and keep the chip paused, outside of a subroutine.
+     */
+    pub fn step_out(&mut self) -> bool {
+        if self.sp == 0 {
+            return false;
+        }
+        self.debugger.run(Target::Out(self.sp));
+        true
+    }
+
+    /**
+     * Read an operand of a condition or a watchpoint.
+     */
+    pub fn operand(&self, op: Operand) -> u16 {
+        match op {
+            Operand::V(x) => self.v[(x & 0xF) as usize] as u16,
+            Operand::I => self.i,
+            Operand::Dt => self.dt as u16,
+            Operand::St => self.st as u16,
+            Operand::Sp => self.sp as u16,
+            Operand::Mem(addr) => self.mem.get(addr as usize).copied().unwrap_or(0) as u16,
+        }
+    }
+
+    pub fn check_condition(&self, cond: &Condition) -> bool {
+        let lhs = self.operand(cond.lhs);
+        match cond.op {
+            CmpOp::Eq => lhs == cond.rhs,
+            CmpOp::Ne => lhs != cond.rhs,
+            CmpOp::Lt => lhs < cond.rhs,
+            CmpOp::Le => lhs <= cond.rhs,
+            CmpOp::Gt => lhs > cond.rhs,
+            CmpOp::Ge => lhs >= cond.rhs,
+        }
+    }
+
+    fn word_at(&self, addr: u16) -> Option<u16> {
+        let a = addr as usize;
+        if a + 1 < self.mem.len() {
+            Some(u16::from_be_bytes([self.mem[a], self.mem[a + 1]]))
+        } else {
+            None
+        }
+    }
+
+    /**
+     * @brief Memory accessed by an instruction, if executed now.
+     *
+     * Return the range of bytes, and whether they are written.
+     * F000 NNNN reads the word following it, at PC + 2.
+     */
+    pub fn mem_access(&self, inst: &ChipInst) -> Option<(Range<usize>, bool)> {
+        if inst.is_long() {
+            let pc = self.pc as usize + 2;
+            return Some((pc..pc + 2, false));
+        }
+        let i = self.i as usize;
+        let (len, write) = match (inst.i, inst.n, inst.nn) {
+            (0x5, 0x2, _) => (
+                (inst.x as i32 - inst.y as i32).unsigned_abs() as usize + 1,
+                true,
+            ),
+            (0x5, 0x3, _) => (
+                (inst.x as i32 - inst.y as i32).unsigned_abs() as usize + 1,
+                false,
+            ),
+            (0xd, n, _) => {
+                let len = if n == 0 { 32 } else { n as usize };
+                (len * self.disp.selected_iter().count(), false)
+            }
+            (0xf, _, 0x02) if inst.x == 0 => (16, false),
+            (0xf, _, 0x33) => (3, true),
+            (0xf, _, 0x55) => (inst.x as usize + 1, true),
+            (0xf, _, 0x65) => (inst.x as usize + 1, false),
+            _ => return None,
+        };
+        Some((i..i + len, write))
+    }
+
+    /**
+     * Return true if the breakpoint at `pc`, if any, holds.
+     */
+    fn breakpoint_hit(&self) -> bool {
+        match self.debugger.breakpoints.get(&self.pc) {
+            Some(Some(cond)) => self.check_condition(cond),
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    /**
+     * @brief Execute an instruction, checking the breakpoints and watchpoints.
+     */
+    fn debug_step(&mut self) -> Option<StopReason> {
+        let resumed = std::mem::take(&mut self.debugger.resumed);
+        if !resumed && self.breakpoint_hit() {
+            return Some(StopReason::Breakpoint(self.pc));
+        }
+
+        // Snapshot what the watchpoints look at
+        let addr = self.pc;
+        let access = self
+            .word_at(addr)
+            .and_then(|w| self.mem_access(&ChipInst::new(w)));
+        let before: Vec<Option<u16>> = self
+            .debugger
+            .watchpoints
+            .iter()
+            .map(|w| match w {
+                Watchpoint::Register(op) => Some(self.operand(*op)),
+                _ => None,
+            })
+            .collect();
+
+        if let Err(e) = self.step() {
+            return Some(StopReason::Error(e));
+        }
+
+        let hit = self
+            .debugger
+            .watchpoints
+            .iter()
+            .zip(before)
+            .position(|(w, old)| match w {
+                Watchpoint::Register(op) => old != Some(self.operand(*op)),
+                Watchpoint::Memory {
+                    range,
+                    access: kind,
+                } => match &access {
+                    Some((bytes, write)) => {
+                        let overlap =
+                            bytes.start < range.end as usize && (range.start as usize) < bytes.end;
+                        let matches = match kind {
+                            Access::Read => !write,
+                            Access::Write => *write,
+                            Access::Any => true,
+                        };
+                        overlap && matches
+                    }
+                    None => false,
+                },
+            });
+        if let Some(index) = hit {
+            return Some(StopReason::Watchpoint { index, addr });
+        }
+        if self.exit {
+            return Some(StopReason::Exited);
+        }
+
+        let done = match self.debugger.target.as_mut() {
+            Some(Target::Steps(n)) => {
+                *n = n.saturating_sub(1);
+                *n == 0
+            }
+            Some(Target::Return { pc, sp }) => self.pc == *pc && self.sp == *sp,
+            Some(Target::Out(sp)) => self.sp < *sp,
+            Some(Target::Addr(a)) => self.pc == *a,
+            Some(Target::Forever) | None => false,
+        };
+        if done {
+            Some(StopReason::Step)
+        } else {
+            None
+        }
+    }
+
+    /**
+     * @brief Run one 60Hz frame under the control of the debugger.
+     *
+     * Behave as `run_frame`, but stop at breakpoints, watchpoints
+     * and once the target of the last command is reached.
+     * Nothing is executed, and the timers are frozen, while the
+     * chip is paused.
+     * Return why the chip stopped, if it did during this frame.
+     */
+    pub fn debug_frame(&mut self, cycles: u32) -> Option<StopReason> {
+        if !self.debugger.is_running() {
+            return None;
+        }
+        if self.exit {
+            self.debugger.target = None;
+            return Some(StopReason::Exited);
+        }
+
+        self.begin_frame();
+        let mut stop = None;
+        let mut executed = 0;
+        while !self.frame_over(executed, cycles) {
+            stop = self.debug_step();
+            executed += 1;
+            if stop.is_some() {
+                break;
+            }
+        }
+        if stop.is_some() {
+            self.debugger.target = None;
+        }
+        self.update_timers();
+        self.refresh_buzzer();
+        if let Err(e) = self.present_frame() {
+            stop = stop.or(Some(StopReason::Error(e)));
+        }
+        stop
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::V(x) => write!(f, "V{:X}", x),
+            Operand::I => write!(f, "I"),
+            Operand::Dt => write!(f, "DT"),
+            Operand::St => write!(f, "ST"),
+            Operand::Sp => write!(f, "SP"),
+            Operand::Mem(addr) => write!(f, "[{:#05x}]", addr),
+        }
+    }
+}
+
+impl fmt::Display for CmpOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            CmpOp::Eq => "==",
+            CmpOp::Ne => "!=",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {:#x}", self.lhs, self.op, self.rhs)
+    }
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Step => write!(f, "Step done"),
+            StopReason::Breakpoint(addr) => write!(f, "Breakpoint at {:#05x}", addr),
+            StopReason::Watchpoint { index, addr } => {
+                write!(f, "Watchpoint {} triggered at {:#05x}", index, addr)
+            }
+            StopReason::Exited => write!(f, "Program exited"),
+            StopReason::Error(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/**
+ * Parse a decimal or hexadecimal (0x) number.
+ */
+pub fn parse_number(s: &str) -> Result<u16, String> {
+    let res = match s.strip_prefix("0x").or(s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse::<u16>(),
+    };
+    res.map_err(|e| format!("Invalid number '{}': {}", s, e))
+}
+
+impl FromStr for Operand {
+    type Err = String;
+
+    /**
+     * Parse `V0` - `VF`, `I`, `DT`, `ST`, `SP` or `[addr]`.
+     */
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let up = s.to_uppercase();
+        if let Some(addr) = up.strip_prefix('[').and_then(|a| a.strip_suffix(']')) {
+            return Ok(Operand::Mem(parse_number(&addr.to_lowercase())?));
+        }
+        match up.as_str() {
+            "I" => Ok(Operand::I),
+            "DT" => Ok(Operand::Dt),
+            "ST" => Ok(Operand::St),
+            "SP" => Ok(Operand::Sp),
+            _ => match up.strip_prefix('V') {
+                Some(x) if x.len() == 1 => u8::from_str_radix(x, 16)
+                    .map(Operand::V)
+                    .map_err(|_| format!("Unknown register '{}'", s)),
+                _ => Err(format!("Unknown register '{}'", s)),
+            },
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    /**
+     * Parse a condition such as `V3 == 0x10` or `[0x300] != 0`.
+     */
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const OPS: [(&str, CmpOp); 6] = [
+            ("==", CmpOp::Eq),
+            ("!=", CmpOp::Ne),
+            ("<=", CmpOp::Le),
+            (">=", CmpOp::Ge),
+            ("<", CmpOp::Lt),
+            (">", CmpOp::Gt),
+        ];
+        for (text, op) in OPS {
+            if let Some((lhs, rhs)) = s.split_once(text) {
+                return Ok(Condition {
+                    lhs: lhs.trim().parse()?,
+                    op,
+                    rhs: parse_number(rhs.trim())?,
+                });
+            }
+        }
+        Err(format!("Invalid condition '{}'", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Access, StopReason, Watchpoint};
+    use crate::chip8::HeadlessChip8;
+
+    /**
+     * Run a program until the watchpoint on `range` stops it.
+     */
+    fn watch(rom: &[u8], range: std::ops::Range<u16>, access: Access) -> Option<u16> {
+        let mut chip = HeadlessChip8::new_headless();
+        chip.load_program(rom).unwrap();
+        chip.debugger_mut()
+            .add_watchpoint(Watchpoint::Memory { range, access });
+        chip.debugger_mut().resume();
+        match chip.debug_frame(10) {
+            Some(StopReason::Watchpoint { addr, .. }) => Some(addr),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn long_index_reads_its_operand() {
+        // I := long 0x0300; loop
+        let rom = [0x12, 0x02, 0xF0, 0x00, 0x03, 0x00, 0x12, 0x06];
+        assert_eq!(watch(&rom, 0x204..0x206, Access::Read), Some(0x202));
+        assert_eq!(watch(&rom, 0x205..0x206, Access::Any), Some(0x202));
+        assert_eq!(watch(&rom, 0x204..0x206, Access::Write), None);
+        // The memory at I is not read
+        assert_eq!(watch(&rom, 0x300..0x302, Access::Read), None);
+    }
+
+    #[test]
+    fn save_writes_at_index() {
+        // I := 0x300; save v1; loop
+        let rom = [0xA3, 0x00, 0xF1, 0x55, 0x12, 0x04];
+        assert_eq!(watch(&rom, 0x301..0x302, Access::Write), Some(0x202));
+        assert_eq!(watch(&rom, 0x302..0x303, Access::Write), None);
+    }
+
+    #[test]
+    fn step_out_needs_a_subroutine() {
+        // call 0x206; loop; v0 := 1; return
+        let rom = [0x22, 0x06, 0x12, 0x02, 0x00, 0x00, 0x60, 0x01, 0x00, 0xEE];
+        let mut chip = HeadlessChip8::new_headless();
+        chip.load_program(&rom).unwrap();
+        assert!(!chip.step_out());
+        assert!(!chip.debugger().is_running());
+
+        chip.debugger_mut().step(1);
+        assert!(matches!(chip.debug_frame(10), Some(StopReason::Step)));
+        assert_eq!(chip.pc(), 0x206);
+        assert!(chip.step_out());
+        assert!(matches!(chip.debug_frame(10), Some(StopReason::Step)));
+        assert_eq!(chip.pc(), 0x202);
+        assert!(!chip.step_out());
+    }
+
+    #[test]
+    fn exited_chip_does_not_step() {
+        // exit
+        let mut chip = HeadlessChip8::new_headless();
+        chip.load_program(&[0x00, 0xFD]).unwrap();
+        chip.debugger_mut().resume();
+        assert!(matches!(chip.debug_frame(10), Some(StopReason::Exited)));
+        let pc = chip.pc();
+        chip.debugger_mut().resume();
+        assert!(matches!(chip.debug_frame(10), Some(StopReason::Exited)));
+        assert_eq!(chip.pc(), pc);
+        assert!(!chip.debugger().is_running());
+    }
+}
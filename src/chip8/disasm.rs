@@ -0,0 +1,480 @@
+use super::instruction::ChipInst;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/**
+ * @brief A line of the disassembly.
+ */
+pub struct DisasmLine {
+    pub addr: u16,             // Address of the first byte
+    pub bytes: Vec<u8>,        // Raw bytes of the instruction
+    pub text: String,          // Mnemonic, with labels for jump and call targets
+    pub label: Option<String>, // Label of the address, if it is a target
+}
+
+impl ChipInst {
+    /**
+     * @brief Write the mnemonic of the instruction.
+     *
+     * Addresses are written with `addr`, so that callers can
+     * replace them with labels.
+     */
+    pub fn mnemonic(&self, addr: &dyn Fn(u16) -> String) -> String {
+        self.decode(addr).unwrap_or_else(|| self.raw())
+    }
+
+    /**
+     * Return true if the instruction is known.
+     */
+    pub fn is_valid(&self) -> bool {
+        self.decode(&|a| a.to_string()).is_some()
+    }
+
+    fn decode(&self, addr: &dyn Fn(u16) -> String) -> Option<String> {
+        let (x, y, n, nn, nnn) = (self.x, self.y, self.n, self.nn, self.nnn);
+        let text = match self.i {
+            0x0 => match nnn {
+                0x0E0 => "CLS".to_string(),
+                0x0EE => "RET".to_string(),
+                0x0FB => "SCR".to_string(),
+                0x0FC => "SCL".to_string(),
+                0x0FD => "EXIT".to_string(),
+                0x0FE => "LOW".to_string(),
+                0x0FF => "HIGH".to_string(),
+                _ if nnn & 0xFF0 == 0x0C0 => format!("SCD {}", n),
+                _ if nnn & 0xFF0 == 0x0D0 => format!("SCU {}", n),
+                // Machine code routines of the VIP are not supported,
+                // any other 0NNN is data
+                _ => return None,
+            },
+            0x1 => format!("JP {}", addr(nnn)),
+            0x2 => format!("CALL {}", addr(nnn)),
+            0x3 => format!("SE V{:X}, 0x{:02X}", x, nn),
+            0x4 => format!("SNE V{:X}, 0x{:02X}", x, nn),
+            0x5 => match n {
+                0x0 => format!("SE V{:X}, V{:X}", x, y),
+                0x2 => format!("SAVE V{:X} - V{:X}", x, y),
+                0x3 => format!("LOAD V{:X} - V{:X}", x, y),
+                _ => return None,
+            },
+            0x6 => format!("LD V{:X}, 0x{:02X}", x, nn),
+            0x7 => format!("ADD V{:X}, 0x{:02X}", x, nn),
+            0x8 => match n {
+                0x0 => format!("LD V{:X}, V{:X}", x, y),
+                0x1 => format!("OR V{:X}, V{:X}", x, y),
+                0x2 => format!("AND V{:X}, V{:X}", x, y),
+                0x3 => format!("XOR V{:X}, V{:X}", x, y),
+                0x4 => format!("ADD V{:X}, V{:X}", x, y),
+                0x5 => format!("SUB V{:X}, V{:X}", x, y),
+                0x6 => format!("SHR V{:X}, V{:X}", x, y),
+                0x7 => format!("SUBN V{:X}, V{:X}", x, y),
+                0xe => format!("SHL V{:X}, V{:X}", x, y),
+                _ => return None,
+            },
+            0x9 if n == 0 => format!("SNE V{:X}, V{:X}", x, y),
+            0xa => format!("LD I, {}", addr(nnn)),
+            0xb => format!("JP V0, {}", addr(nnn)),
+            0xc => format!("RND V{:X}, 0x{:02X}", x, nn),
+            0xd => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            0xe => match nn {
+                0x9E => format!("SKP V{:X}", x),
+                0xA1 => format!("SKNP V{:X}", x),
+                _ => return None,
+            },
+            0xf => match nn {
+                0x00 if x == 0 => "LD I, long".to_string(),
+                0x01 => format!("PLANE {}", x),
+                0x02 if x == 0 => "AUDIO".to_string(),
+                0x07 => format!("LD V{:X}, DT", x),
+                0x0a => format!("LD V{:X}, K", x),
+                0x15 => format!("LD DT, V{:X}", x),
+                0x18 => format!("LD ST, V{:X}", x),
+                0x1e => format!("ADD I, V{:X}", x),
+                0x29 => format!("LD F, V{:X}", x),
+                0x30 => format!("LD HF, V{:X}", x),
+                0x33 => format!("LD B, V{:X}", x),
+                0x3a => format!("PITCH V{:X}", x),
+                0x55 => format!("LD [I], V{:X}", x),
+                0x65 => format!("LD V{:X}, [I]", x),
+                0x75 => format!("LD R, V{:X}", x),
+                0x85 => format!("LD V{:X}, R", x),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(text)
+    }
+
+    /**
+     * Raw word, for data or unknown instructions.
+     */
+    fn raw(&self) -> String {
+        format!("DW 0x{:04X}", self.full)
+    }
+
+    /**
+     * Return true if this is F000, followed by a 16 bits address.
+     */
+    pub fn is_long(&self) -> bool {
+        self.full == 0xF000
+    }
+
+    /**
+     * Address targeted by a direct jump or call.
+     */
+    pub fn jump_target(&self) -> Option<u16> {
+        match self.i {
+            0x1 | 0x2 => Some(self.nnn),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ChipInst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mnemonic(&|a| format!("0x{:03X}", a)))
+    }
+}
+
+impl fmt::Display for DisasmLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(label) = &self.label {
+            writeln!(f, "{}:", label)?;
+        }
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(
+            f,
+            "    0x{:03X}:  {:<12} {}",
+            self.addr,
+            bytes.join(" "),
+            self.text
+        )
+    }
+}
+
+/**
+ * Name of the label of a jump or call target.
+ */
+pub fn label_name(addr: u16) -> String {
+    format!("L{:03X}", addr)
+}
+
+/**
+ * Decode the word at `off` in `rom`, if there are two bytes left.
+ */
+fn word_at(rom: &[u8], off: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*rom.get(off)?, *rom.get(off + 1)?]))
+}
+
+/**
+ * @brief Disassemble a program with a linear sweep.
+ *
+ * Every pair of bytes is decoded as an instruction, starting at
+ * `start`, the address where the program is loaded.
+ */
+pub fn disassemble(rom: &[u8], start: u16) -> Vec<DisasmLine> {
+    // First pass, decode instructions
+    let mut decoded = Vec::new();
+    let mut off = 0;
+    while off < rom.len() {
+        let addr = start.wrapping_add(off as u16);
+        let inst = match word_at(rom, off) {
+            Some(w) => ChipInst::new(w),
+            None => {
+                // A single byte left, it cannot be an instruction
+                decoded.push((addr, rom[off..].to_vec(), None, None));
+                break;
+            }
+        };
+        let long = if inst.is_long() {
+            word_at(rom, off + 2)
+        } else {
+            None
+        };
+        let len = if long.is_some() { 4 } else { 2 };
+        decoded.push((addr, rom[off..off + len].to_vec(), Some(inst), long));
+        off += len;
+    }
+
+    // Jump and call targets inside the program get a label
+    let targets: BTreeSet<u16> = decoded
+        .iter()
+        .filter_map(|(_, _, inst, _)| inst.as_ref()?.jump_target())
+        .filter(|t| decoded.iter().any(|(a, _, _, _)| a == t))
+        .collect();
+    let name = |a: u16| {
+        if targets.contains(&a) {
+            label_name(a)
+        } else {
+            format!("0x{:03X}", a)
+        }
+    };
+
+    // Second pass, write the mnemonics
+    decoded
+        .into_iter()
+        .map(|(addr, bytes, inst, long)| {
+            let text = match (inst, long) {
+                (Some(_), Some(l)) => format!("LD I, 0x{:04X}", l),
+                (Some(inst), None) => inst.mnemonic(&name),
+                (None, _) => format!("DB 0x{:02X}", bytes[0]),
+            };
+            DisasmLine {
+                addr,
+                label: targets.get(&addr).map(|a| label_name(*a)),
+                bytes,
+                text,
+            }
+        })
+        .collect()
+}
+
+/**
+ * @brief What a byte of the program has been found to be.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ByteKind {
+    Data,    // Never reached
+    Code,    // First byte of an instruction
+    Operand, // Other bytes of an instruction
+    Sprite,  // Drawn by DXYN
+}
+
+/**
+ * Render a sprite byte as a bitmap.
+ */
+fn bitmap(byte: u8) -> String {
+    (0..8)
+        .map(|b| if byte & (0x80 >> b) != 0 { '#' } else { '.' })
+        .collect()
+}
+
+/**
+ * @brief Disassemble a program by following its control flow.
+ *
+ * Starting at `start`, jumps, calls, returns and skips are followed,
+ * so that only reachable bytes are decoded as instructions.
+ * Bytes drawn by a DXYN, with I known from a previous ANNN,
+ * are marked as sprite data and shown as bitmaps.
+ * Indirect BNNN jumps cannot be followed, they are flagged as unresolved.
+ */
+pub fn disassemble_flow(rom: &[u8], start: u16) -> Vec<DisasmLine> {
+    let len = rom.len();
+    let offset = |addr: u16| {
+        let off = addr.wrapping_sub(start) as usize;
+        if addr >= start && off < len {
+            Some(off)
+        } else {
+            None
+        }
+    };
+
+    let mut kinds = vec![ByteKind::Data; len];
+    let mut targets = BTreeSet::new(); // Jump and call targets
+    let mut sprites = BTreeSet::new(); // Sprite addresses
+    let mut sprite_ranges = Vec::new();
+    let mut unresolved = BTreeSet::new(); // Indirect jumps
+
+    // Each path carries the value of I, if known
+    let mut work: Vec<(u16, Option<u16>)> = vec![(start, None)];
+    while let Some((mut addr, mut i_reg)) = work.pop() {
+        loop {
+            let off = match offset(addr) {
+                Some(off) if off + 1 < len && kinds[off] != ByteKind::Code => off,
+                _ => break,
+            };
+            let inst = ChipInst::new(word_at(rom, off).unwrap());
+            let size = if inst.is_long() { 4 } else { 2 };
+            kinds[off] = ByteKind::Code;
+            for k in kinds.iter_mut().take((off + size).min(len)).skip(off + 1) {
+                *k = ByteKind::Operand;
+            }
+            let next = addr.wrapping_add(size as u16);
+
+            // Size of the instruction after this one, to know where skips land
+            let skipped = match offset(next).and_then(|o| word_at(rom, o)) {
+                Some(0xF000) => next.wrapping_add(4),
+                _ => next.wrapping_add(2),
+            };
+
+            match (inst.i, inst.n, inst.nn) {
+                // Return and exit end the path
+                (0x0, _, 0xEE) | (0x0, _, 0xFD) if inst.x == 0 => break,
+                (0x1, _, _) => {
+                    targets.insert(inst.nnn);
+                    addr = inst.nnn;
+                    continue;
+                }
+                (0x2, _, _) => {
+                    // The callee may change I
+                    targets.insert(inst.nnn);
+                    work.push((inst.nnn, i_reg));
+                    i_reg = None;
+                }
+                (0x3, _, _) | (0x4, _, _) | (0x5, 0x0, _) | (0x9, 0x0, _) => {
+                    work.push((skipped, i_reg));
+                }
+                (0xe, _, 0x9E) | (0xe, _, 0xA1) => work.push((skipped, i_reg)),
+                (0xa, _, _) => i_reg = Some(inst.nnn),
+                (0xb, _, _) => {
+                    unresolved.insert(addr);
+                    break;
+                }
+                (0xd, n, _) => {
+                    if let Some(i) = i_reg {
+                        let rows = if n == 0 { 32 } else { n as u16 };
+                        sprites.insert(i);
+                        sprite_ranges.push((i, rows));
+                    }
+                }
+                (0xf, _, 0x00) if inst.x == 0 => {
+                    i_reg = offset(addr.wrapping_add(2)).and_then(|o| word_at(rom, o));
+                }
+                (0xf, _, 0x1e) | (0xf, _, 0x29) | (0xf, _, 0x30) => i_reg = None,
+                (0xf, _, 0x55) | (0xf, _, 0x65) => i_reg = None,
+                // Unknown instructions are most likely data
+                _ if !inst.is_valid() => break,
+                _ => {}
+            }
+            addr = next;
+        }
+    }
+
+    // Sprites only take bytes that are not code
+    for (i, rows) in sprite_ranges {
+        for addr in i..i.saturating_add(rows) {
+            if let Some(off) = offset(addr) {
+                if kinds[off] == ByteKind::Data {
+                    kinds[off] = ByteKind::Sprite;
+                }
+            }
+        }
+    }
+    sprites.retain(|a| offset(*a).map(|o| kinds[o]) == Some(ByteKind::Sprite));
+    targets.retain(|a| offset(*a).map(|o| kinds[o]) == Some(ByteKind::Code));
+
+    let name = |a: u16| {
+        if targets.contains(&a) {
+            label_name(a)
+        } else if sprites.contains(&a) {
+            sprite_name(a)
+        } else {
+            format!("0x{:03X}", a)
+        }
+    };
+    let label = |a: u16| {
+        if targets.contains(&a) {
+            Some(label_name(a))
+        } else if sprites.contains(&a) {
+            Some(sprite_name(a))
+        } else {
+            None
+        }
+    };
+
+    // Write the lines, in the order of the program
+    let mut lines = Vec::new();
+    let mut off = 0;
+    while off < len {
+        let addr = start.wrapping_add(off as u16);
+        let (size, text) = match kinds[off] {
+            ByteKind::Code if off + 1 < len => {
+                let inst = ChipInst::new(word_at(rom, off).unwrap());
+                if inst.is_long() && off + 3 < len {
+                    let l = word_at(rom, off + 2).unwrap();
+                    (4, format!("LD I, {}", name(l)))
+                } else if unresolved.contains(&addr) {
+                    (
+                        2,
+                        format!("{}  ; unresolved indirect jump", inst.mnemonic(&name)),
+                    )
+                } else {
+                    (2, inst.mnemonic(&name))
+                }
+            }
+            ByteKind::Sprite => (1, format!("DB 0x{:02X}  ; {}", rom[off], bitmap(rom[off]))),
+            _ => {
+                // Group up to 8 bytes of data, until something else starts
+                let mut end = off + 1;
+                while end < len
+                    && end - off < 8
+                    && matches!(kinds[end], ByteKind::Data | ByteKind::Operand)
+                    && label(start.wrapping_add(end as u16)).is_none()
+                {
+                    end += 1;
+                }
+                let bytes: Vec<String> = rom[off..end]
+                    .iter()
+                    .map(|b| format!("0x{:02X}", b))
+                    .collect();
+                (end - off, format!("DB {}", bytes.join(", ")))
+            }
+        };
+        lines.push(DisasmLine {
+            addr,
+            bytes: rom[off..off + size].to_vec(),
+            text,
+            label: label(addr),
+        });
+        off += size;
+    }
+    lines
+}
+
+/**
+ * Name of the label of a sprite.
+ */
+pub fn sprite_name(addr: u16) -> String {
+    format!("S{:03X}", addr)
+}
+
+/**
+ * Turn disassembled lines into a printable listing.
+ */
+pub fn listing(lines: &[DisasmLine]) -> String {
+    lines.iter().map(|l| format!("{}\n", l)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disassemble, disassemble_flow};
+    use crate::chip8::ChipInst;
+
+    #[test]
+    fn mnemonics() {
+        assert_eq!(ChipInst::new(0x631F).to_string(), "LD V3, 0x1F");
+        assert_eq!(ChipInst::new(0xD015).to_string(), "DRW V0, V1, 5");
+        assert_eq!(ChipInst::new(0xA2EA).to_string(), "LD I, 0x2EA");
+        assert_eq!(ChipInst::new(0x0123).to_string(), "DW 0x0123");
+    }
+
+    #[test]
+    fn machine_code_calls_are_data() {
+        for w in [
+            0x00E0, 0x00EE, 0x00C3, 0x00D2, 0x00FB, 0x00FC, 0x00FD, 0x00FE, 0x00FF,
+        ] {
+            assert!(ChipInst::new(w).is_valid(), "{:04X}", w);
+        }
+        for w in [0x0000, 0x0123, 0x00E1, 0x00B0, 0x0FFF] {
+            assert!(!ChipInst::new(w).is_valid(), "{:04X}", w);
+        }
+    }
+
+    #[test]
+    fn listing() {
+        // jump to the second instruction; data
+        let rom = [0x12, 0x02, 0x01, 0x23];
+        let lines = disassemble(&rom, 0x200);
+        assert_eq!(lines[0].text, "JP L202");
+        assert_eq!(lines[1].label.as_deref(), Some("L202"));
+        assert_eq!(lines[1].text, "DW 0x0123");
+        assert_eq!(
+            lines[1].to_string(),
+            "L202:\n    0x202:  01 23        DW 0x0123"
+        );
+
+        // The flow stops at the data
+        let lines = disassemble_flow(&rom, 0x200);
+        assert_eq!(lines.last().unwrap().text, "DW 0x0123");
+    }
+}
@@ -0,0 +1,65 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/**
+ * @brief Errors that can occur while loading or running a program.
+ *
+ * Addresses are the ones of the faulting instruction.
+ */
+#[derive(Debug)]
+pub enum Chip8Error {
+    UnknownOpcode { addr: u16, opcode: u16 },
+    StackOverflow { addr: u16 },
+    StackUnderflow { addr: u16 },
+    OutOfBounds { addr: u16, access: usize },
+    RomTooLarge { size: usize, max: usize },
+    InvalidState(String),
+    InvalidMovie(String),
+    Backend(String),
+    Io(io::Error),
+}
+
+/**
+ * @brief What to do when an instruction fails.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    Halt,   // Stop the chip and report the error
+    Skip,   // Report the error, but keep the chip running after the instruction
+    Ignore, // Treat the faulting instruction as a no-op
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::UnknownOpcode { addr, opcode } => {
+                write!(f, "Unknown instruction at {:#05x}: {:04x}", addr, opcode)
+            }
+            Chip8Error::StackOverflow { addr } => write!(f, "Stack overflow at {:#05x}", addr),
+            Chip8Error::StackUnderflow { addr } => write!(f, "Stack underflow at {:#05x}", addr),
+            Chip8Error::OutOfBounds { addr, access } => write!(
+                f,
+                "Out of bounds memory access at {:#05x}: {:#x}",
+                addr, access
+            ),
+            Chip8Error::RomTooLarge { size, max } => write!(
+                f,
+                "Program is too large: {} bytes, at most {} fit in memory",
+                size, max
+            ),
+            Chip8Error::InvalidState(e) => write!(f, "Invalid save state: {}", e),
+            Chip8Error::InvalidMovie(e) => write!(f, "Invalid movie: {}", e),
+            Chip8Error::Backend(e) => write!(f, "Backend error: {}", e),
+            Chip8Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for Chip8Error {}
+
+impl From<io::Error> for Chip8Error {
+    fn from(e: io::Error) -> Self {
+        Chip8Error::Io(e)
+    }
+}
@@ -0,0 +1,427 @@
+use super::debugger::{Access, StopReason, Watchpoint};
+use super::scheduler::FrameScheduler;
+use super::{Audio, Chip8, Input, Renderer};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+/**
+ * Registers, in the order of the `g` packet: V0 - VF, I, PC and SP.
+ * I and PC are 16 bits, sent in little endian.
+ */
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+
+/**
+ * @brief GDB remote serial protocol stub, driving a chip.
+ *
+ * The client controls the chip through its debugger: breakpoints
+ * (`Z0`, `Z1`) and watchpoints (`Z2` - `Z4`) are the ones of the
+ * `Debugger`, `c` and `s` resume the chip, and `^C` pauses it.
+ * While running, the chip executes `cycles` instructions per frame.
+ */
+pub struct GdbStub<'a, R: Renderer, A: Audio, I: Input> {
+    chip: &'a mut Chip8<R, A, I>,
+    stream: TcpStream,
+    cycles: u32,
+}
+
+/**
+ * @brief Serve a client connected on `stream`, until it detaches.
+ */
+pub fn serve<R: Renderer, A: Audio, I: Input>(
+    chip: &mut Chip8<R, A, I>,
+    stream: TcpStream,
+    cycles: u32,
+) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    GdbStub {
+        chip,
+        stream,
+        cycles,
+    }
+    .run()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|k| u8::from_str_radix(s.get(k..k + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_hex(s: &str) -> Option<usize> {
+    usize::from_str_radix(s, 16).ok()
+}
+
+/**
+ * Parse the `addr,len` arguments of memory and breakpoint packets.
+ */
+fn addr_len(s: &str) -> Option<(usize, usize)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
+
+impl<'a, R: Renderer, A: Audio, I: Input> GdbStub<'a, R, A, I> {
+    fn run(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            let reply = match packet.as_bytes().first() {
+                Some(b'k') => return Ok(()),
+                Some(b'D') => {
+                    self.send("OK")?;
+                    return Ok(());
+                }
+                Some(b'c') | Some(b's') => {
+                    if let Some(addr) = packet.get(1..).and_then(parse_hex) {
+                        self.chip.pc = addr as u16;
+                    }
+                    if packet.starts_with('c') {
+                        self.chip.debugger_mut().resume();
+                    } else {
+                        self.chip.debugger_mut().step(1);
+                    }
+                    self.resume()?
+                }
+                _ => self.command(&packet),
+            };
+            self.send(&reply)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Answer the packets which do not run the chip.
+     * An empty reply tells that the packet is not supported.
+     */
+    fn command(&mut self, packet: &str) -> String {
+        if packet.is_empty() {
+            return String::new();
+        }
+        let (cmd, args) = packet.split_at(1);
+        let res = match cmd {
+            "?" => Some("S05".to_string()),
+            "g" => Some(hex(&self.registers())),
+            "G" => unhex(args).and_then(|regs| self.set_registers(&regs)),
+            "p" => parse_hex(args)
+                .and_then(|n| self.register(n))
+                .map(|r| hex(&r)),
+            "P" => args.split_once('=').and_then(|(n, v)| {
+                let n = parse_hex(n)?;
+                let mut regs = self.registers();
+                let (start, size) = Self::reg_offset(n)?;
+                let v = unhex(v)?;
+                regs.get_mut(start..start + size)?
+                    .copy_from_slice(v.get(..size)?);
+                self.set_registers(&regs)
+            }),
+            "m" => addr_len(args).and_then(|(addr, len)| {
+                let bytes = self.chip.mem.get(addr..addr.checked_add(len)?)?;
+                Some(hex(bytes))
+            }),
+            "M" => args.split_once(':').and_then(|(range, data)| {
+                let (addr, len) = addr_len(range)?;
+                let data = unhex(data)?;
+                let mem = self.chip.mem.get_mut(addr..addr.checked_add(len)?)?;
+                mem.copy_from_slice(data.get(..len)?);
+                Some("OK".to_string())
+            }),
+            "Z" | "z" => return self.breakpoint(cmd == "Z", args),
+            "H" => Some("OK".to_string()),
+            "q" if args.starts_with("Supported") => Some("PacketSize=1000".to_string()),
+            "q" if args == "Attached" => Some("1".to_string()),
+            _ => return String::new(),
+        };
+        res.unwrap_or_else(|| "E01".to_string())
+    }
+
+    /**
+     * Insert or remove a breakpoint or a watchpoint: `Ztype,addr,kind`.
+     */
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let parsed = args
+            .split_once(',')
+            .and_then(|(kind, rest)| Some((kind.parse::<u8>().ok()?, addr_len(rest)?)));
+        let (kind, (addr, len)) = match parsed {
+            Some(p) => p,
+            None => return "E01".to_string(),
+        };
+        let addr = addr as u16;
+        let access = match kind {
+            0 | 1 => {
+                let debugger = self.chip.debugger_mut();
+                if insert {
+                    debugger.add_breakpoint(addr, None);
+                } else {
+                    debugger.remove_breakpoint(addr);
+                }
+                return "OK".to_string();
+            }
+            2 => Access::Write,
+            3 => Access::Read,
+            4 => Access::Any,
+            _ => return String::new(),
+        };
+
+        let watch = Watchpoint::Memory {
+            range: addr..addr.saturating_add(len.max(1) as u16),
+            access,
+        };
+        let debugger = self.chip.debugger_mut();
+        if insert {
+            debugger.add_watchpoint(watch);
+        } else if let Some(index) = debugger.watchpoints().iter().position(|w| *w == watch) {
+            debugger.remove_watchpoint(index);
+        }
+        "OK".to_string()
+    }
+
+    fn registers(&self) -> Vec<u8> {
+        let chip = &self.chip;
+        let mut regs = chip.v.to_vec();
+        regs.extend_from_slice(&chip.i.to_le_bytes());
+        regs.extend_from_slice(&chip.pc.to_le_bytes());
+        regs.push(chip.sp);
+        regs
+    }
+
+    fn set_registers(&mut self, regs: &[u8]) -> Option<String> {
+        if regs.len() < 16 + 2 + 2 + 1 || regs[20] as usize >= self.chip.stack.len() {
+            return None;
+        }
+        self.chip.v.copy_from_slice(&regs[..16]);
+        self.chip.i = u16::from_le_bytes([regs[16], regs[17]]);
+        self.chip.pc = u16::from_le_bytes([regs[18], regs[19]]);
+        self.chip.sp = regs[20];
+        Some("OK".to_string())
+    }
+
+    /**
+     * Offset and size of a register in the `g` packet.
+     */
+    fn reg_offset(n: usize) -> Option<(usize, usize)> {
+        match n {
+            0..=15 => Some((n, 1)),
+            REG_I => Some((16, 2)),
+            REG_PC => Some((18, 2)),
+            REG_SP => Some((20, 1)),
+            _ => None,
+        }
+    }
+
+    fn register(&self, n: usize) -> Option<Vec<u8>> {
+        let (start, size) = Self::reg_offset(n)?;
+        Some(self.registers()[start..start + size].to_vec())
+    }
+
+    /**
+     * @brief Run the chip until it stops, or the client interrupts it.
+     *
+     * Return the stop reply packet.
+     */
+    fn resume(&mut self) -> io::Result<String> {
+        self.stream.set_nonblocking(true)?;
+        let mut scheduler = FrameScheduler::default();
+        let reply = loop {
+            if let Some(stop) = self.chip.debug_frame(self.cycles) {
+                break self.stop_reply(stop);
+            }
+
+            // Check for a ^C from the client
+            let mut byte = [0u8; 1];
+            match self.stream.read(&mut byte) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(_) if byte[0] == 0x03 => {
+                    self.chip.debugger_mut().pause();
+                    break "S02".to_string();
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+            scheduler.wait();
+        };
+        self.stream.set_nonblocking(false)?;
+        Ok(reply)
+    }
+
+    fn stop_reply(&self, stop: StopReason) -> String {
+        match stop {
+            StopReason::Step | StopReason::Breakpoint(_) => "S05".to_string(),
+            StopReason::Watchpoint { index, .. } => {
+                match &self.chip.debugger().watchpoints()[index] {
+                    Watchpoint::Memory { range, access } => {
+                        let kind = match access {
+                            Access::Write => "watch",
+                            Access::Read => "rwatch",
+                            Access::Any => "awatch",
+                        };
+                        format!("T05{}:{:x};", kind, range.start)
+                    }
+                    Watchpoint::Register(_) => "S05".to_string(),
+                }
+            }
+            StopReason::Exited => "W00".to_string(),
+            StopReason::Error(_) => "S04".to_string(),
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0u8; 1];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    /**
+     * @brief Read the next `$data#checksum` packet, and acknowledge it.
+     *
+     * Return None once the client is gone.
+     */
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Skip the acknowledgments, and the ^C sent while paused
+            match self.read_byte()? {
+                Some(b'$') => {}
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(b) => data.push(b),
+                    None => return Ok(None),
+                }
+            }
+            let mut sum = [0u8; 2];
+            for c in sum.iter_mut() {
+                match self.read_byte()? {
+                    Some(b) => *c = b,
+                    None => return Ok(None),
+                }
+            }
+
+            let expected = data.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+            let sum = std::str::from_utf8(&sum)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok());
+            if sum == Some(expected) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let sum = data.bytes().fold(0u8, |acc, b| acc.wrapping_add(b));
+        write!(self.stream, "${}#{:02x}", data, sum)?;
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::serve;
+    use crate::chip8::HeadlessChip8;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    fn checksum(data: &str) -> u8 {
+        data.bytes().fold(0u8, |acc, b| acc.wrapping_add(b))
+    }
+
+    fn read_byte(stream: &mut TcpStream) -> u8 {
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    /**
+     * Send a packet, check it is acknowledged, and return the reply.
+     */
+    fn request(stream: &mut TcpStream, packet: &str) -> String {
+        write!(stream, "${}#{:02x}", packet, checksum(packet)).unwrap();
+        assert_eq!(read_byte(stream), b'+', "ack of {}", packet);
+
+        assert_eq!(read_byte(stream), b'$');
+        let mut data = String::new();
+        loop {
+            match read_byte(stream) {
+                b'#' => break,
+                b => data.push(b as char),
+            }
+        }
+        let sum = [read_byte(stream), read_byte(stream)];
+        let sum = u8::from_str_radix(std::str::from_utf8(&sum).unwrap(), 16).unwrap();
+        assert_eq!(sum, checksum(&data), "checksum of {}", data);
+        stream.write_all(b"+").unwrap();
+        data
+    }
+
+    #[test]
+    fn scripted_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut s = TcpStream::connect(addr).unwrap();
+
+            // A corrupted packet is refused
+            s.write_all(b"$g#00").unwrap();
+            assert_eq!(read_byte(&mut s), b'-');
+
+            assert_eq!(request(&mut s, "?"), "S05");
+            let regs = format!("{}0000000200", "00".repeat(16));
+            assert_eq!(request(&mut s, "g"), regs);
+            let regs = format!("0022{}3412000200", "00".repeat(14));
+            assert_eq!(request(&mut s, &format!("G{}", regs)), "OK");
+            assert_eq!(request(&mut s, "p1"), "22");
+            assert_eq!(request(&mut s, "p10"), "3412");
+            assert_eq!(request(&mut s, "p11"), "0002");
+            assert_eq!(request(&mut s, "p13"), "E01");
+            assert_eq!(request(&mut s, "P12=1f"), "OK");
+            assert_eq!(request(&mut s, "p12"), "1f");
+            assert_eq!(request(&mut s, "P12=20"), "E01");
+            assert_eq!(request(&mut s, "p12"), "1f");
+            assert_eq!(request(&mut s, "P12=00"), "OK");
+
+            assert_eq!(request(&mut s, "m200,4"), "60057001");
+            assert_eq!(request(&mut s, "M300,2:abcd"), "OK");
+            assert_eq!(request(&mut s, "m300,2"), "abcd");
+            assert_eq!(request(&mut s, "mffff,2"), "E01");
+
+            assert_eq!(request(&mut s, "Z0,204,2"), "OK");
+            assert_eq!(request(&mut s, "c"), "S05");
+            assert_eq!(request(&mut s, "p11"), "0402");
+            assert_eq!(request(&mut s, "p0"), "06");
+            assert_eq!(request(&mut s, "z0,204,2"), "OK");
+            assert_eq!(request(&mut s, "s"), "S05");
+            assert_eq!(request(&mut s, "p11"), "0202");
+
+            assert_eq!(request(&mut s, "D"), "OK");
+        });
+
+        let mut chip = HeadlessChip8::new_headless();
+        // V0 = 5; V0 += 1; jump back
+        chip.load_program(&[0x60, 0x05, 0x70, 0x01, 0x12, 0x02])
+            .unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        serve(&mut chip, stream, 10).unwrap();
+        client.join().unwrap();
+
+        assert_eq!(chip.registers()[1], 0x22);
+        assert_eq!(chip.index(), 0x1234);
+        assert_eq!(chip.memory()[0x300..0x302], [0xAB, 0xCD]);
+    }
+}
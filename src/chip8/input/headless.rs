@@ -0,0 +1,44 @@
+use super::{Input, KeyEvent, Keypad};
+
+/**
+ * @brief Keypad driven by hand, without any event source.
+ */
+pub struct HeadlessInput {
+    keypad: Keypad,
+}
+
+impl HeadlessInput {
+    pub fn new() -> Self {
+        HeadlessInput {
+            keypad: Keypad::new(),
+        }
+    }
+
+    pub fn press(&mut self, key: u8) {
+        self.keypad.set(key, true);
+    }
+
+    pub fn release(&mut self, key: u8) {
+        self.keypad.set(key, false);
+    }
+}
+
+impl Default for HeadlessInput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Input for HeadlessInput {
+    fn is_pressed(&self, key: u8) -> bool {
+        self.keypad.is_pressed(key)
+    }
+
+    fn set_keys(&mut self, keys: u16) {
+        self.keypad.set_keys(keys);
+    }
+
+    fn next_event(&mut self) -> Option<KeyEvent> {
+        self.keypad.next_event()
+    }
+}
@@ -0,0 +1,111 @@
+use sdl2::keyboard::Scancode;
+
+/**
+ * @brief Keys of the keyboard bound to the 16 keys of the keypad.
+ *
+ * The default layout maps the COSMAC VIP keypad on the left
+ * of a QWERTY keyboard, by position:
+ *
+ * ```text
+ * 1 2 3 C      1 2 3 4
+ * 4 5 6 D  ->  Q W E R
+ * 7 8 9 E      A S D F
+ * A 0 B F      Z X C V
+ * ```
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    codes: [Scancode; 16], // Key bound to each keypad key, by value
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap {
+            codes: [
+                Scancode::X,    // 0
+                Scancode::Num1, // 1
+                Scancode::Num2, // 2
+                Scancode::Num3, // 3
+                Scancode::Q,    // 4
+                Scancode::W,    // 5
+                Scancode::E,    // 6
+                Scancode::A,    // 7
+                Scancode::S,    // 8
+                Scancode::D,    // 9
+                Scancode::Z,    // A
+                Scancode::C,    // B
+                Scancode::Num4, // C
+                Scancode::R,    // D
+                Scancode::F,    // E
+                Scancode::V,    // F
+            ],
+        }
+    }
+}
+
+/**
+ * @brief Parse a binding, such as `5 = Up`.
+ *
+ * The keypad key is a hexadecimal digit, the keyboard key
+ * is given by its SDL name, such as `W`, `Keypad 8` or `Space`.
+ */
+pub fn parse_binding(s: &str) -> Result<(u8, Scancode), String> {
+    let (key, name) = s
+        .split_once('=')
+        .ok_or(format!("Invalid binding '{}', expected <key>=<name>", s))?;
+    let key = match u8::from_str_radix(key.trim(), 16) {
+        Ok(k) if k < 0x10 => k,
+        _ => return Err(format!("Invalid keypad key '{}', expected 0-f", key.trim())),
+    };
+    let code = Scancode::from_name(name.trim())
+        .ok_or(format!("Unknown keyboard key '{}'", name.trim()))?;
+    Ok((key, code))
+}
+
+impl Keymap {
+    /**
+     * Keyboard key bound to a keypad key.
+     */
+    pub fn code(&self, key: u8) -> Scancode {
+        self.codes[(key & 0xF) as usize]
+    }
+
+    pub fn codes(&self) -> &[Scancode; 16] {
+        &self.codes
+    }
+
+    pub fn bind(&mut self, key: u8, code: Scancode) {
+        self.codes[(key & 0xF) as usize] = code;
+    }
+
+    /**
+     * @brief Apply the bindings of a keymap file, for a program.
+     *
+     * Each line is a binding, as read by `parse_binding`.
+     * The bindings following a `[name]` line only apply to the
+     * programs whose file is called `name`, such as `[pong.ch8]`.
+     * Empty lines and lines starting with `#` are ignored.
+     */
+    pub fn apply(&mut self, text: &str, program: &str) -> Result<(), String> {
+        let file_name = std::path::Path::new(program)
+            .file_name()
+            .map(|f| f.to_string_lossy());
+        let mut active = true;
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                active = file_name.as_deref() == Some(section.trim());
+                continue;
+            }
+            let (key, code) = parse_binding(line).map_err(|e| format!("line {}: {}", n + 1, e))?;
+            if active {
+                self.bind(key, code);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use super::KeyEvent;
+use std::collections::VecDeque;
+
+/**
+ * Events kept until the program handles them, the oldest are dropped.
+ */
+const MAX_EVENTS: usize = 16;
+
+/**
+ * @brief State of the keys, and the changes not handled yet.
+ *
+ * This is the storage shared by the keypads of the frontends.
+ */
+pub struct Keypad {
+    keys: [bool; 16],
+    events: VecDeque<KeyEvent>, // Changes of the keys, the oldest first
+}
+
+impl Keypad {
+    pub fn new() -> Self {
+        Keypad {
+            keys: [false; 16],
+            events: VecDeque::with_capacity(MAX_EVENTS),
+        }
+    }
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.keys.get(key as usize).copied().unwrap_or(false)
+    }
+
+    /**
+     * Change the state of a key, recording an event if it changed.
+     */
+    pub fn set(&mut self, key: u8, pressed: bool) {
+        let key = key & 0xF;
+        if self.keys[key as usize] == pressed {
+            return;
+        }
+        self.keys[key as usize] = pressed;
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(KeyEvent { key, pressed });
+    }
+
+    pub fn set_keys(&mut self, keys: u16) {
+        for k in 0..16 {
+            self.set(k, keys & (1 << k) != 0);
+        }
+    }
+
+    pub fn next_event(&mut self) -> Option<KeyEvent> {
+        self.events.pop_front()
+    }
+}
+
+impl Default for Keypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyEvent, Keypad};
+
+    #[test]
+    fn short_tap_keeps_both_events() {
+        let mut keypad = Keypad::new();
+        keypad.set(0x5, true);
+        keypad.set(0x5, true);
+        keypad.set(0x5, false);
+        assert!(!keypad.is_pressed(0x5));
+        let press = KeyEvent {
+            key: 0x5,
+            pressed: true,
+        };
+        let release = KeyEvent {
+            key: 0x5,
+            pressed: false,
+        };
+        assert_eq!(keypad.next_event(), Some(press));
+        assert_eq!(keypad.next_event(), Some(release));
+        assert_eq!(keypad.next_event(), None);
+    }
+}
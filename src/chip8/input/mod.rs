@@ -0,0 +1,68 @@
+// Exported modules
+pub mod headless;
+pub mod keymap;
+pub mod keypad;
+pub mod sdl_input;
+
+// Avoid verbosity
+pub use headless::HeadlessInput;
+pub use keymap::Keymap;
+pub use keypad::Keypad;
+pub use sdl_input::SDLInput;
+
+/**
+ * @brief A key of the keypad going down or up.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: u8,       // Key which changed
+    pub pressed: bool, // True if the key went down, false if it was released
+}
+
+/**
+ * @brief State of the 16 keys hexadecimal keypad.
+ *
+ * Keys are identified by their hexadecimal value (0x0 to 0xF).
+ * The keypad is a snapshot filled by the frontend, from a device
+ * or from a recorded movie, so that the chip never polls a device.
+ */
+pub trait Input {
+    /**
+     * Return true if the key `key` is currently held down.
+     */
+    fn is_pressed(&self, key: u8) -> bool;
+
+    /**
+     * Replace the state of the whole keypad,
+     * with bit `k` of `keys` set if the key `k` is held down.
+     */
+    fn set_keys(&mut self, keys: u16);
+
+    /**
+     * Return the state of the whole keypad, as given to `set_keys`.
+     */
+    fn keys(&self) -> u16 {
+        (0..16)
+            .filter(|k| self.is_pressed(*k))
+            .fold(0, |acc, k| acc | (1 << k))
+    }
+
+    /**
+     * Pop the oldest change of the keypad not handled yet.
+     */
+    fn next_event(&mut self) -> Option<KeyEvent>;
+
+    /**
+     * Drop the changes not handled yet.
+     */
+    fn clear_events(&mut self) {
+        while self.next_event().is_some() {}
+    }
+
+    /**
+     * Return the first key currently held down, if any.
+     */
+    fn pressed_key(&self) -> Option<u8> {
+        (0..16).find(|k| self.is_pressed(*k))
+    }
+}
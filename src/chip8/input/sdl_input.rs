@@ -0,0 +1,74 @@
+use super::{Input, KeyEvent, Keymap, Keypad};
+
+use sdl2::event::Event;
+
+/**
+ * @brief Keypad state read from SDL's keyboard.
+ *
+ * The state follows the keyboard events given to `handle_event`,
+ * so that a key pressed and released within a frame is still seen.
+ */
+pub struct SDLInput {
+    keypad: Keypad,
+    keymap: Keymap, // Keyboard key bound to each keypad key
+}
+
+impl SDLInput {
+    pub fn new() -> Self {
+        SDLInput {
+            keypad: Keypad::new(),
+            keymap: Keymap::default(),
+        }
+    }
+
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    /**
+     * Update the keypad from a keyboard event polled from SDL.
+     * Events of keys not bound to the keypad are ignored.
+     */
+    pub fn handle_event(&mut self, event: &Event) {
+        let (code, pressed) = match event {
+            Event::KeyDown {
+                scancode: Some(code),
+                ..
+            } => (code, true),
+            Event::KeyUp {
+                scancode: Some(code),
+                ..
+            } => (code, false),
+            _ => return,
+        };
+        for (key, bound) in self.keymap.codes().iter().enumerate() {
+            if bound == code {
+                self.keypad.set(key as u8, pressed);
+            }
+        }
+    }
+}
+
+impl Default for SDLInput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Input for SDLInput {
+    fn is_pressed(&self, key: u8) -> bool {
+        self.keypad.is_pressed(key)
+    }
+
+    fn set_keys(&mut self, keys: u16) {
+        self.keypad.set_keys(keys);
+    }
+
+    fn next_event(&mut self) -> Option<KeyEvent> {
+        self.keypad.next_event()
+    }
+}
@@ -1,8 +1,4 @@
-use super::input;
-use super::Chip8;
-use rand::random;
-
-use sdl2::EventPump;
+use super::{Audio, Chip8, Chip8Error, Input, KeyWait, Renderer};
 
 #[derive(Debug)]
 pub struct ChipInst {
@@ -32,237 +28,427 @@ impl ChipInst {
 /**
  * Define all instruction functions
  */
-impl Chip8 {
-    pub fn execute(&mut self, inst: &ChipInst, ev_pump: Option<&EventPump>) {
+impl<R: Renderer, A: Audio, I: Input> Chip8<R, A, I> {
+    /**
+     * @brief Execute a decoded instruction.
+     *
+     * Failures are handled according to the error policy of the chip.
+     */
+    pub fn execute(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Match the first half-byte
-        match inst.i {
+        let res = match inst.i {
             0x0 => match inst.nnn {
-                0x0E0 => self.inst_00E0(inst, ev_pump),
-                0x0EE => self.inst_00EE(inst, ev_pump),
-                _ => panic!(
-                    "Unknown instruction at {:#03x}: {:04x}",
-                    self.pc - 2,
-                    inst.full
-                ),
+                0x0E0 => self.inst_00E0(inst),
+                0x0EE => self.inst_00EE(inst),
+                0x0FB => self.inst_00FB(inst),
+                0x0FC => self.inst_00FC(inst),
+                0x0FD => self.inst_00FD(inst),
+                0x0FE => self.inst_00FE(inst),
+                0x0FF => self.inst_00FF(inst),
+                nnn if nnn & 0xFF0 == 0x0C0 => self.inst_00CN(inst),
+                nnn if nnn & 0xFF0 == 0x0D0 => self.inst_00DN(inst),
+                _ => Err(self.unknown_opcode(inst)),
+            },
+            0x1 => self.inst_1NNN(inst),
+            0x2 => self.inst_2NNN(inst),
+            0x3 => self.inst_3XNN(inst),
+            0x4 => self.inst_4XNN(inst),
+            0x5 => match inst.n {
+                0x0 => self.inst_5XY0(inst),
+                0x2 => self.inst_5XY2(inst),
+                0x3 => self.inst_5XY3(inst),
+                _ => Err(self.unknown_opcode(inst)),
             },
-            0x1 => self.inst_1NNN(inst, ev_pump),
-            0x2 => self.inst_2NNN(inst, ev_pump),
-            0x3 => self.inst_3XNN(inst, ev_pump),
-            0x4 => self.inst_4XNN(inst, ev_pump),
-            0x5 => self.inst_5XY0(inst, ev_pump),
-            0x6 => self.inst_6XNN(inst, ev_pump),
-            0x7 => self.inst_7XNN(inst, ev_pump),
+            0x6 => self.inst_6XNN(inst),
+            0x7 => self.inst_7XNN(inst),
             0x8 => match inst.n {
-                0x0 => self.inst_8XY0(inst, ev_pump),
-                0x1 => self.inst_8XY1(inst, ev_pump),
-                0x2 => self.inst_8XY2(inst, ev_pump),
-                0x3 => self.inst_8XY3(inst, ev_pump),
-                0x4 => self.inst_8XY4(inst, ev_pump),
-                0x5 => self.inst_8XY5(inst, ev_pump),
-                0x6 => self.inst_8XY6(inst, ev_pump),
-                0x7 => self.inst_8XY7(inst, ev_pump),
-                0xe => self.inst_8XYE(inst, ev_pump),
-                0xA1 => self.inst_EXA1(inst, ev_pump),
-                _ => panic!(
-                    "Unknown instruction at {:#03x}: {:04x}",
-                    self.pc - 2,
-                    inst.full
-                ),
+                0x0 => self.inst_8XY0(inst),
+                0x1 => self.inst_8XY1(inst),
+                0x2 => self.inst_8XY2(inst),
+                0x3 => self.inst_8XY3(inst),
+                0x4 => self.inst_8XY4(inst),
+                0x5 => self.inst_8XY5(inst),
+                0x6 => self.inst_8XY6(inst),
+                0x7 => self.inst_8XY7(inst),
+                0xe => self.inst_8XYE(inst),
+                _ => Err(self.unknown_opcode(inst)),
             },
-            0x9 => self.inst_9XY0(inst, ev_pump),
-            0xa => self.inst_ANNN(inst, ev_pump),
-            0xb => self.inst_BNNN(inst, ev_pump),
-            0xc => self.inst_CXNN(inst, ev_pump),
-            0xd => self.inst_DXYN(inst, ev_pump),
+            0x9 => self.inst_9XY0(inst),
+            0xa => self.inst_ANNN(inst),
+            0xb => self.inst_BNNN(inst),
+            0xc => self.inst_CXNN(inst),
+            0xd => self.inst_DXYN(inst),
             0xe => match inst.nn {
-                0x9E => self.inst_EX9E(inst, ev_pump),
-                0xA1 => self.inst_EXA1(inst, ev_pump),
-                _ => panic!(
-                    "Unknown instruction at {:#03x}: {:04x}",
-                    self.pc - 2,
-                    inst.full
-                ),
+                0x9E => self.inst_EX9E(inst),
+                0xA1 => self.inst_EXA1(inst),
+                _ => Err(self.unknown_opcode(inst)),
             },
             0xf => match inst.nn {
-                0x07 => self.inst_FX07(inst, ev_pump),
-                0x0a => self.inst_FX0A(inst, ev_pump),
-                0x15 => self.inst_FX15(inst, ev_pump),
-                0x18 => self.inst_FX18(inst, ev_pump),
-                0x1e => self.inst_FX1E(inst, ev_pump),
-                0x29 => self.inst_FX29(inst, ev_pump),
-                0x33 => self.inst_FX33(inst, ev_pump),
-                0x55 => self.inst_FX55(inst, ev_pump),
-                0x65 => self.inst_FX65(inst, ev_pump),
-                _ => panic!(
-                    "Unknown instruction at {:#03x}: {:04x}",
-                    self.pc - 2,
-                    inst.full
-                ),
+                0x00 if inst.x == 0 => self.inst_F000(inst),
+                0x01 => self.inst_FN01(inst),
+                0x02 if inst.x == 0 => self.inst_F002(inst),
+                0x07 => self.inst_FX07(inst),
+                0x0a => self.inst_FX0A(inst),
+                0x15 => self.inst_FX15(inst),
+                0x18 => self.inst_FX18(inst),
+                0x1e => self.inst_FX1E(inst),
+                0x29 => self.inst_FX29(inst),
+                0x30 => self.inst_FX30(inst),
+                0x33 => self.inst_FX33(inst),
+                0x3a => self.inst_FX3A(inst),
+                0x55 => self.inst_FX55(inst),
+                0x65 => self.inst_FX65(inst),
+                0x75 => self.inst_FX75(inst),
+                0x85 => self.inst_FX85(inst),
+                _ => Err(self.unknown_opcode(inst)),
             },
-            _ => panic!(
-                "Unknown instruction at {:#03x}: {:04x}",
-                self.pc - 2,
-                inst.full
-            ),
+            _ => Err(self.unknown_opcode(inst)),
+        };
+
+        res.or_else(|e| self.handle_error(e))
+    }
+
+    /**
+     * Address of the instruction being executed.
+     */
+    fn inst_addr(&self) -> u16 {
+        self.pc.wrapping_sub(2)
+    }
+
+    fn unknown_opcode(&self, inst: &ChipInst) -> Chip8Error {
+        Chip8Error::UnknownOpcode {
+            addr: self.inst_addr(),
+            opcode: inst.full,
         }
     }
 
     /**
-     * All instructions have the same signature for potential
-     * function pointer stuff later
+     * Skip the next instruction.
+     * F000 NNNN is the only instruction taking 4 bytes.
+     */
+    fn skip_next(&mut self) {
+        let pc = self.pc as usize;
+        let long = pc + 1 < self.mem.len() && self.mem[pc] == 0xF0 && self.mem[pc + 1] == 0x00;
+        self.pc = self.pc.wrapping_add(if long { 4 } else { 2 });
+    }
+
+    /**
+     * Show the framebuffer on the renderer.
      */
+    pub(super) fn present(&mut self) -> Result<(), Chip8Error> {
+        self.renderer
+            .render(&self.disp)
+            .map_err(Chip8Error::Backend)
+    }
+
+    /**
+     * Mark the framebuffer as changed, to present it at the end of the frame.
+     */
+    fn invalidate(&mut self) -> Result<(), Chip8Error> {
+        self.dirty = true;
+        Ok(())
+    }
+
+    /**
+     * Check that `len` bytes starting at `addr` are in memory.
+     */
+    fn check_mem(&self, addr: usize, len: usize) -> Result<(), Chip8Error> {
+        if addr + len > self.mem.len() {
+            return Err(Chip8Error::OutOfBounds {
+                addr: self.inst_addr(),
+                access: addr + len - 1,
+            });
+        }
+        Ok(())
+    }
+
+    // All instructions have the same signature for potential
+    // function pointer stuff later
 
     #[allow(non_snake_case)]
-    fn inst_00E0(&mut self, _inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_00E0(&mut self, _inst: &ChipInst) -> Result<(), Chip8Error> {
         // Just clear the screen
         self.disp.clear();
-        self.disp.render();
+        self.invalidate()
+    }
+
+    #[allow(non_snake_case)]
+    fn inst_00CN(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
+        // Scroll the screen N pixels down
+        self.disp.scroll_down(inst.n);
+        self.invalidate()
+    }
+
+    #[allow(non_snake_case)]
+    fn inst_00DN(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
+        // Scroll the screen N pixels up
+        self.disp.scroll_up(inst.n);
+        self.invalidate()
     }
 
     #[allow(non_snake_case)]
-    fn inst_00EE(&mut self, _inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_00EE(&mut self, _inst: &ChipInst) -> Result<(), Chip8Error> {
         // 'ret' instruction
+        if self.sp == 0 {
+            return Err(Chip8Error::StackUnderflow {
+                addr: self.inst_addr(),
+            });
+        }
         self.pc = self.stack[self.sp as usize];
         self.sp -= 1;
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    fn inst_00FB(&mut self, _inst: &ChipInst) -> Result<(), Chip8Error> {
+        // Scroll the screen 4 pixels right
+        self.disp.scroll_right(4);
+        self.invalidate()
+    }
+
+    #[allow(non_snake_case)]
+    fn inst_00FC(&mut self, _inst: &ChipInst) -> Result<(), Chip8Error> {
+        // Scroll the screen 4 pixels left
+        self.disp.scroll_left(4);
+        self.invalidate()
+    }
+
+    #[allow(non_snake_case)]
+    fn inst_00FD(&mut self, _inst: &ChipInst) -> Result<(), Chip8Error> {
+        // Exit the interpreter
+        self.exit = true;
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    fn inst_00FE(&mut self, _inst: &ChipInst) -> Result<(), Chip8Error> {
+        // Switch to the 64x32 low resolution mode
+        self.disp.set_hires(false);
+        self.invalidate()
+    }
+
+    #[allow(non_snake_case)]
+    fn inst_00FF(&mut self, _inst: &ChipInst) -> Result<(), Chip8Error> {
+        // Switch to the 128x64 high resolution mode
+        self.disp.set_hires(true);
+        self.invalidate()
     }
 
     #[allow(non_snake_case)]
-    fn inst_1NNN(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_1NNN(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Simple jump
         self.pc = inst.nnn;
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_2NNN(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_2NNN(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Function call
+        if self.sp as usize + 1 >= self.stack.len() {
+            return Err(Chip8Error::StackOverflow {
+                addr: self.inst_addr(),
+            });
+        }
         self.sp += 1;
         self.stack[self.sp as usize] = self.pc;
         self.pc = inst.nnn;
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_3XNN(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_3XNN(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Skip next instruction if Vx == NN
         if self.v[inst.x as usize] == inst.nn {
-            self.pc += 2;
+            self.skip_next();
         }
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_4XNN(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_4XNN(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Skip next instruction if Vx != NN
         if self.v[inst.x as usize] != inst.nn {
-            self.pc += 2;
+            self.skip_next();
         }
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_5XY0(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_5XY0(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Skip next instruction if Vx == Vy
         if self.v[inst.x as usize] == self.v[inst.y as usize] {
-            self.pc += 2
+            self.skip_next();
+        }
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    fn inst_5XY2(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
+        // Store Vx up to Vy from I, without altering I
+        // (Vy is included, and the order is reversed if x > y)
+        let regs = Self::reg_range(inst);
+        let i: usize = self.i as usize;
+        self.check_mem(i, regs.len())?;
+        for (k, r) in regs.into_iter().enumerate() {
+            self.mem[i + k] = self.v[r];
+        }
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    fn inst_5XY3(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
+        // Load memory from I in Vx up to Vy, without altering I
+        // (Vy is included, and the order is reversed if x > y)
+        let regs = Self::reg_range(inst);
+        let i: usize = self.i as usize;
+        self.check_mem(i, regs.len())?;
+        for (k, r) in regs.into_iter().enumerate() {
+            self.v[r] = self.mem[i + k];
+        }
+        Ok(())
+    }
+
+    /**
+     * Registers from Vx to Vy, in this order.
+     */
+    fn reg_range(inst: &ChipInst) -> Vec<usize> {
+        let (x, y) = (inst.x as usize, inst.y as usize);
+        if x <= y {
+            (x..=y).collect()
+        } else {
+            (y..=x).rev().collect()
         }
     }
 
     #[allow(non_snake_case)]
-    fn inst_6XNN(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_6XNN(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Set Vx to NN
         self.v[inst.x as usize] = inst.nn;
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_7XNN(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_7XNN(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Set Vx to Vx + NN with no carry set
         let vx = &mut self.v[inst.x as usize];
         *vx = (*vx).wrapping_add(inst.nn);
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_8XY0(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_8XY0(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Set Vx to Vy
         self.v[inst.x as usize] = self.v[inst.y as usize];
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_8XY1(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_8XY1(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Set Vx to Vx | Vy
         self.v[inst.x as usize] |= self.v[inst.y as usize];
+        if self.config.logic_vf_reset {
+            self.v[0xF] = 0;
+        }
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_8XY2(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_8XY2(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Set Vx to Vx & Vy
         self.v[inst.x as usize] &= self.v[inst.y as usize];
+        if self.config.logic_vf_reset {
+            self.v[0xF] = 0;
+        }
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_8XY3(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_8XY3(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Set Vx to Vx ^ Vy
         self.v[inst.x as usize] ^= self.v[inst.y as usize];
+        if self.config.logic_vf_reset {
+            self.v[0xF] = 0;
+        }
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_8XY4(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_8XY4(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Set Vx to Vx + Vy (with carry set)
         let x: u16 = self.v[inst.x as usize] as u16 + self.v[inst.y as usize] as u16;
         self.v[inst.x as usize] = x as u8;
-        self.v[0xF] = if x > (1 << 8) { 1 } else { 0 };
+        self.v[0xF] = if x > 0xFF { 1 } else { 0 };
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_8XY5(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_8XY5(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Set Vx to Vx - Vy and set carry to 0 if Vy > Vx
         let xx = self.v[inst.x as usize];
         let yy = self.v[inst.y as usize];
         let carry = if yy > xx { 0 } else { 1 };
         self.v[inst.x as usize] = xx.wrapping_sub(yy);
         self.v[0xF] = carry;
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_8XY6(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
-        // Set Vx to Vy, then shift Vx by 1 on the
+    fn inst_8XY6(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
+        // Set Vx to Vy (legacy) or Vx, then shift Vx by 1 on the
         // right and set carry to the shifted out bit
-        let y = &mut self.v[inst.y as usize];
-        *y >>= 1;
-        self.v[0xF] = *y & 0x01;
+        let src = if self.config.shift_legacy {
+            self.v[inst.y as usize]
+        } else {
+            self.v[inst.x as usize]
+        };
+        self.v[inst.x as usize] = src >> 1;
+        self.v[0xF] = src & 0x01;
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_8XY7(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_8XY7(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Set Vx to Vy - Vx and set carry to 0 if Vx > Vy
         let xx = self.v[inst.x as usize];
         let yy = self.v[inst.y as usize];
         let carry = if yy < xx { 0 } else { 1 };
         self.v[inst.x as usize] = yy.wrapping_sub(xx);
         self.v[0xF] = carry;
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_8XYE(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
-        // Set Vx to Vy, then shift Vx by 1 on the
+    fn inst_8XYE(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
+        // Set Vx to Vy (legacy) or Vx, then shift Vx by 1 on the
         // left and set carry to the shifted out bit
-        let y = &mut self.v[inst.x as usize];
-        *y <<= 1;
-        self.v[0xF] = *y & 0x80;
+        let src = if self.config.shift_legacy {
+            self.v[inst.y as usize]
+        } else {
+            self.v[inst.x as usize]
+        };
+        self.v[inst.x as usize] = src << 1;
+        self.v[0xF] = src >> 7;
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_9XY0(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_9XY0(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Skip next instruction if Vx != Vy
         if self.v[inst.x as usize] != self.v[inst.y as usize] {
-            self.pc = self.pc.wrapping_add(2);
+            self.skip_next();
         }
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_ANNN(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_ANNN(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Set I to NNN
         self.i = inst.nnn;
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_BNNN(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_BNNN(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Warning, legacy and modern implementation differ
         if self.config.off_jump_legacy {
             // Legacy: Set PC to V0 + NNN
@@ -271,100 +457,175 @@ impl Chip8 {
             // Modern: Set PC to VX + XNN
             self.pc = (self.v[inst.x as usize] as u16).wrapping_add(inst.nnn);
         }
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_CXNN(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_CXNN(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Set Vx to NN & random
-        self.v[inst.x as usize] = random::<u8>() & inst.nn;
+        self.v[inst.x as usize] = self.rng.next_u8() & inst.nn;
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_DXYN(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_DXYN(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Draw a sprite on the screen, starting at coordinates
-        // (Vx % 64, Vy % 32), being N pixel tall and 8 pixels large,
+        // (Vx % width, Vy % height), being N pixel tall and 8 pixels large,
         // taking sprites from mem[I]
-        let vx = self.v[inst.x as usize] % 64;
-        let vy = self.v[inst.y as usize] % 32;
-
-        for i in 0..inst.n {
-            if vy + i >= 32 {
-                break;
+        // If N is 0, the sprite is 16x16, with two bytes per row
+        // Each selected plane is drawn in turn, with its own sprite data
+        // VF is set to 1 if any lit pixel is turned off
+        let (width, height) = (self.disp.width(), self.disp.height());
+        let vx = self.v[inst.x as usize] % width;
+        let vy = self.v[inst.y as usize] % height;
+        let wrap = self.config.sprite_wrap;
+        let (rows, row_len) = if inst.n == 0 { (16, 2) } else { (inst.n, 1) };
+        let sprite_len = (rows as usize) * row_len;
+        let planes: Vec<usize> = self.disp.selected_iter().collect();
+        self.check_mem(self.i as usize, sprite_len * planes.len())?;
+
+        let mut collision = false;
+        for (k, plane) in planes.into_iter().enumerate() {
+            let base = self.i as usize + k * sprite_len;
+            for i in 0..rows {
+                // Rows going past the bottom are clipped, or wrapped
+                // on the top if enabled
+                if vy as u16 + i as u16 >= height as u16 && !wrap {
+                    break;
+                }
+                let y = ((vy as u16 + i as u16) % height as u16) as u8;
+                let addr = base + (i as usize) * row_len;
+                collision |= if row_len == 2 {
+                    let word = u16::from_be_bytes([self.mem[addr], self.mem[addr + 1]]);
+                    self.disp.draw_sprite16(plane, vx, y, word, wrap)
+                } else {
+                    self.disp.draw_sprite(plane, vx, y, self.mem[addr], wrap)
+                };
             }
-            self.disp.draw_sprite(
-                vx,
-                vy.wrapping_add(i),
-                self.mem[(self.i + (i as u16)) as usize],
-            )
+        }
+        self.v[0xF] = collision as u8;
+
+        // If enabled, wait for the next frame before going on
+        // The VIP always waits for the display interrupt
+        if self.config.display_wait || self.config.vip_timing {
+            self.vblank_wait = true;
         }
 
         // Update display
-        self.disp.render();
+        self.invalidate()
     }
 
     #[allow(non_snake_case)]
-    fn inst_EX9E(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_EX9E(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Skip next instruction if the key Vx is pressed
-        // Check if an event pump is provided
-        if let Some(ep) = _ep {
-            if input::is_pressed(ep, self.v[inst.x as usize]) {
-                self.pc += 2
-            }
+        if self.input.is_pressed(self.v[inst.x as usize]) {
+            self.skip_next();
         }
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_EXA1(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_EXA1(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Skip next instruction if the key Vx is not pressed
-        // Check if an event pump is provided
-        if let Some(ep) = _ep {
-            if !input::is_pressed(ep, self.v[inst.x as usize]) {
-                self.pc += 2
-            }
+        if !self.input.is_pressed(self.v[inst.x as usize]) {
+            self.skip_next();
         }
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    fn inst_F000(&mut self, _inst: &ChipInst) -> Result<(), Chip8Error> {
+        // Set I to the 16 bits word following the instruction
+        let pc = self.pc as usize;
+        self.check_mem(pc, 2)?;
+        self.i = u16::from_be_bytes([self.mem[pc], self.mem[pc + 1]]);
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    fn inst_FN01(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
+        // Select the planes N drawn on by the following instructions
+        self.disp.select_planes(inst.x);
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    fn inst_F002(&mut self, _inst: &ChipInst) -> Result<(), Chip8Error> {
+        // Load the 16 bytes audio pattern from mem[I]
+        let i: usize = self.i as usize;
+        self.check_mem(i, 16)?;
+        let mut pattern = [0; 16];
+        pattern.copy_from_slice(&self.mem[i..i + 16]);
+        self.pattern = Some(pattern);
+        self.refresh_pattern();
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_FX07(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_FX07(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Set Vx to DT
         self.v[inst.x as usize] = self.dt;
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_FX0A(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
-        // Get the pressed key and put it in Vx
-        // Check if an event pump is provided
-        if let Some(ep) = &_ep {
+    fn inst_FX0A(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
+        if !self.config.key_release_wait {
+            // Get the pressed key and put it in Vx
             // Check if there is a key currently pressed
-            if let Some(u) = input::get_scancode(ep) {
+            if let Some(u) = self.input.pressed_key() {
                 self.v[inst.x as usize] = u;
             }
             // If no key is pressed, wait for one to be pressed
             else {
                 self.pc = self.pc.wrapping_sub(2);
             }
+            return Ok(());
+        }
+
+        // Only the keys pressed after the wait started count
+        if self.key_wait == KeyWait::Idle {
+            self.input.clear_events();
+            self.key_wait = KeyWait::Press;
+        }
+        while let Some(event) = self.input.next_event() {
+            match self.key_wait {
+                KeyWait::Press if event.pressed => self.key_wait = KeyWait::Release(event.key),
+                KeyWait::Release(key) if key == event.key && !event.pressed => {
+                    self.v[inst.x as usize] = key;
+                    self.key_wait = KeyWait::Idle;
+                    return Ok(());
+                }
+                _ => {}
+            }
         }
+        // Execute FX0A again until the key is released
+        self.pc = self.pc.wrapping_sub(2);
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_FX15(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_FX15(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Set DT to Vx
         self.dt = self.v[inst.x as usize];
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_FX18(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_FX18(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Set ST to Vx
         self.st = self.v[inst.x as usize];
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_FX1E(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_FX1E(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Add Vx to I
         // if enabled, set carry bit to 1 if I goes
         // from 0x0FFF to 0x1000+
         let save_i = self.i;
-        self.i += self.v[inst.x as usize] as u16;
+        self.i = self.i.wrapping_add(self.v[inst.x as usize] as u16);
 
         // If needed, set the carry bit
         if self.config.index_add_carry {
@@ -374,35 +635,59 @@ impl Chip8 {
                 0
             };
         }
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_FX29(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_FX29(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Put I at the address of the font character in Vx
         let vx = self.v[inst.x as usize];
 
         // font_start is the first drawing byte of '0',
         // and there are 5 bytes per character
         self.i = self.config.font_start + ((vx as u16) * 5);
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    fn inst_FX30(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
+        // Put I at the address of the big font character in Vx
+        let vx = self.v[inst.x as usize] & 0xF;
+
+        // There are 10 bytes per big character
+        self.i = self.config.big_font_start + ((vx as u16) * 10);
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_FX33(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_FX33(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Put the decimal digit values of Vx into
         // I, I + 1, and I + 2
         // Should never overflow as max value of Vx is 255
         let vx = self.v[inst.x as usize];
         let i: usize = self.i as usize;
+        self.check_mem(i, 3)?;
         self.mem[i] = vx / 100;
         self.mem[i + 1] = (vx / 10) % 10;
         self.mem[i + 2] = vx % 10;
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    fn inst_FX3A(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
+        // Set the pitch of the audio pattern to Vx
+        // Until F002 loads a pattern, the tone keeps playing
+        self.pitch = self.v[inst.x as usize];
+        self.refresh_pattern();
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_FX55(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_FX55(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Store V0 up to Vx from I to I + x
         // (Vx is included)
         let i: usize = self.i as usize;
+        self.check_mem(i, (inst.x as usize) + 1)?;
         for k in 0..((inst.x as usize) + 1) {
             self.mem[i + k] = self.v[k];
         }
@@ -410,15 +695,17 @@ impl Chip8 {
         // If enabled, set the index to follow the
         // legacy behavior
         if self.config.reg_save_legacy {
-            self.i += (inst.x as u16) + 1;
+            self.i = self.i.wrapping_add((inst.x as u16) + 1);
         }
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    fn inst_FX65(&mut self, inst: &ChipInst, _ep: Option<&EventPump>) {
+    fn inst_FX65(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
         // Load memory I to I + x in V0 to Vx
         // (Vx is included)
         let i: usize = self.i as usize;
+        self.check_mem(i, (inst.x as usize) + 1)?;
         for k in 0..((inst.x as usize) + 1) {
             self.v[k] = self.mem[i + k];
         }
@@ -426,7 +713,272 @@ impl Chip8 {
         // If enabled, set the index to follow the
         // legacy behavior
         if self.config.reg_save_legacy {
-            self.i += (inst.x as u16) + 1;
+            self.i = self.i.wrapping_add((inst.x as u16) + 1);
+        }
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    fn inst_FX75(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
+        // Store V0 up to Vx in the RPL user flags
+        // (Vx is included)
+        let n = (inst.x as usize) + 1;
+        self.rpl[..n].copy_from_slice(&self.v[..n]);
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    fn inst_FX85(&mut self, inst: &ChipInst) -> Result<(), Chip8Error> {
+        // Load the RPL user flags in V0 to Vx
+        // (Vx is included)
+        let n = (inst.x as usize) + 1;
+        self.v[..n].copy_from_slice(&self.rpl[..n]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::{Chip8Error, ErrorPolicy, HeadlessChip8, Profile};
+
+    /**
+     * Load a program made of `words` and execute `steps` instructions.
+     */
+    fn run(chip: &mut HeadlessChip8, words: &[u16], steps: usize) {
+        let rom: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+        chip.load_program(&rom).unwrap();
+        for _ in 0..steps {
+            chip.step().unwrap();
         }
     }
+
+    fn chip(profile: Option<Profile>) -> HeadlessChip8 {
+        let mut chip = HeadlessChip8::new_headless();
+        chip.load_default_font();
+        if let Some(profile) = profile {
+            chip.set_profile(profile);
+        }
+        chip
+    }
+
+    #[test]
+    fn draw_and_collide() {
+        let mut c = chip(None);
+        // V0 = 0; I = font of '0'; draw 5 rows at (V0, V0)
+        run(&mut c, &[0x6000, 0xF029, 0xD005], 3);
+        assert_eq!(c.registers()[0xF], 0);
+        assert!(c.framebuffer().pixel(0, 0) && c.framebuffer().pixel(3, 4));
+        assert!(!c.framebuffer().pixel(1, 1) && !c.framebuffer().pixel(4, 0));
+
+        // Drawing it again erases it
+        c.execute(&super::ChipInst::new(0xD005)).unwrap();
+        assert_eq!(c.registers()[0xF], 1);
+        assert!(!c.framebuffer().pixel(0, 0));
+    }
+
+    #[test]
+    fn draw_clip_and_wrap() {
+        for wrap in [false, true] {
+            let mut c = chip(None);
+            c.config_mut().sprite_wrap = wrap;
+            // V0 = 60 + 64; V1 = 30; I = sprite; draw 5 rows
+            let words = [0x607C, 0x611E, 0xA208, 0xD015, 0xFFFF, 0xFFFF, 0xFF00];
+            run(&mut c, &words, 4);
+            let disp = c.framebuffer();
+            // The coordinates wrap, the sprite starts at (60, 30)
+            assert!(disp.pixel(60, 30) && disp.pixel(63, 31));
+            assert_eq!(disp.pixel(0, 30), wrap);
+            assert_eq!(disp.pixel(60, 0), wrap);
+            assert_eq!(disp.pixel(63, 2), wrap);
+        }
+    }
+
+    #[test]
+    fn draw_big_sprite() {
+        let mut c = chip(None);
+        // hires; V0 = 2; I = big font of '0'; draw 16x16
+        run(&mut c, &[0x00FF, 0x6002, 0xF030, 0xD000], 4);
+        let disp = c.framebuffer();
+        assert!(disp.is_hires());
+        assert_eq!(c.registers()[0xF], 0);
+        assert!((2..18).any(|y| disp.pixel(2 + 2, y)));
+        assert!((2..18).any(|x| disp.pixel(x, 2 + 8)));
+    }
+
+    #[test]
+    fn add_and_shift_flags() {
+        let mut c = chip(None);
+        // V1 = 0xFF; V2 = 1; V1 += V2
+        run(&mut c, &[0x61FF, 0x6201, 0x8124], 3);
+        assert_eq!((c.registers()[1], c.registers()[0xF]), (0, 1));
+        // V1 += V2 again, without carry
+        c.execute(&super::ChipInst::new(0x8124)).unwrap();
+        assert_eq!((c.registers()[1], c.registers()[0xF]), (1, 0));
+
+        let mut c = chip(Some(Profile::SuperChip));
+        // V1 = 0x81; V1 <<= V1
+        run(&mut c, &[0x6181, 0x811E], 2);
+        assert_eq!((c.registers()[1], c.registers()[0xF]), (0x02, 1));
+        c.execute(&super::ChipInst::new(0x811E)).unwrap();
+        assert_eq!((c.registers()[1], c.registers()[0xF]), (0x04, 0));
+        c.execute(&super::ChipInst::new(0x8116)).unwrap();
+        assert_eq!((c.registers()[1], c.registers()[0xF]), (0x02, 0));
+    }
+
+    #[test]
+    fn flag_written_last() {
+        // The flag wins over the result when VF is the destination
+        let mut c = chip(None);
+        // VF = 0xFF; V1 = 1; VF += V1
+        run(&mut c, &[0x6FFF, 0x6101, 0x8F14], 3);
+        assert_eq!(c.registers()[0xF], 1);
+        let mut c = chip(None);
+        // VF = 0x10; V1 = 1; VF += V1
+        run(&mut c, &[0x6F10, 0x6101, 0x8F14], 3);
+        assert_eq!(c.registers()[0xF], 0);
+
+        for profile in [Profile::Vip, Profile::SuperChip] {
+            // VF = NN, then VF <<= VF or VF >>= VF: the shifted out bit is kept
+            for (words, flag) in [
+                ([0x6F81, 0x8FFE], 1),
+                ([0x6F40, 0x8FFE], 0),
+                ([0x6F81, 0x8FF6], 1),
+                ([0x6F40, 0x8FF6], 0),
+            ] {
+                let mut c = chip(Some(profile));
+                run(&mut c, &words, 2);
+                assert_eq!(c.registers()[0xF], flag, "{} {:04X?}", profile, words);
+            }
+        }
+
+        // With the legacy shift, the bit comes from Vy
+        let mut c = chip(Some(Profile::Vip));
+        // V1 = 0x80; V2 = 0x01; V2 <<= V1
+        run(&mut c, &[0x6180, 0x6201, 0x821E], 3);
+        assert_eq!((c.registers()[2], c.registers()[0xF]), (0, 1));
+    }
+
+    #[test]
+    fn profile_quirks() {
+        // Jump with V0, I moved by FX55, VF reset by 8XY1, shift of
+        // Vy, sprite wrap and display wait
+        let expected = [
+            (Profile::Vip, [true, true, true, true, false, true]),
+            (Profile::Chip48, [false, true, false, false, false, false]),
+            (
+                Profile::SuperChip,
+                [false, false, false, false, false, false],
+            ),
+            (Profile::XoChip, [true, true, false, true, true, false]),
+        ];
+        for (profile, quirks) in expected {
+            let mut c = chip(Some(profile));
+            // V0 = 0x10; V3 = 0x20; jump0 0x300
+            run(&mut c, &[0x6010, 0x6320, 0xB300], 3);
+            assert_eq!(c.pc() == 0x310, quirks[0], "{}", profile);
+
+            let mut c = chip(Some(profile));
+            // I = 0x300; save v2
+            run(&mut c, &[0xA300, 0xF255], 2);
+            assert_eq!(c.index() == 0x303, quirks[1], "{}", profile);
+
+            let mut c = chip(Some(profile));
+            // VF = 5; V1 |= V2
+            run(&mut c, &[0x6F05, 0x8121], 2);
+            assert_eq!(c.registers()[0xF] == 0, quirks[2], "{}", profile);
+
+            let mut c = chip(Some(profile));
+            // V2 = 4; V1 >>= V2
+            run(&mut c, &[0x6204, 0x8126], 2);
+            assert_eq!(c.registers()[1] == 2, quirks[3], "{}", profile);
+
+            let mut c = chip(Some(profile));
+            // V0 = 60; I = sprite; draw
+            run(&mut c, &[0x603C, 0xA206, 0xD005, 0xFFFF, 0xFFFF, 0xFF00], 3);
+            assert_eq!(c.framebuffer().pixel(0, 0), quirks[4], "{}", profile);
+            assert_eq!(c.vblank_wait, quirks[5], "{}", profile);
+        }
+    }
+
+    #[test]
+    fn index_add_carry() {
+        for carry in [false, true] {
+            let mut c = chip(None);
+            c.config_mut().index_add_carry = carry;
+            // I = 0xFFF; V0 = 1; VF = 7; I += V0
+            run(&mut c, &[0xAFFF, 0x6001, 0x6F07, 0xF01E], 4);
+            assert_eq!(c.index(), 0x1000);
+            assert_eq!(c.registers()[0xF], if carry { 1 } else { 7 });
+        }
+    }
+
+    #[test]
+    fn error_policies() {
+        // return without call; V1 = 5
+        let rom = [0x00, 0xEE, 0x61, 0x05];
+        let underflow = |r| matches!(r, Err(Chip8Error::StackUnderflow { addr: 0x200 }));
+
+        let mut c = chip(None);
+        c.load_program(&rom).unwrap();
+        assert!(underflow(c.step()));
+        assert!(c.has_exited());
+
+        let mut c = chip(None);
+        c.set_error_policy(ErrorPolicy::Skip);
+        c.load_program(&rom).unwrap();
+        assert!(underflow(c.step()));
+        assert!(!c.has_exited());
+        c.step().unwrap();
+        assert_eq!(c.registers()[1], 5);
+
+        let mut c = chip(None);
+        c.set_error_policy(ErrorPolicy::Ignore);
+        c.load_program(&rom).unwrap();
+        c.step().unwrap();
+        c.step().unwrap();
+        assert!(!c.has_exited());
+        assert_eq!(c.registers()[1], 5);
+    }
+
+    #[test]
+    fn key_wait_press_then_release() {
+        let mut c = chip(None);
+        // A key held before the wait does not count
+        c.input_mut().press(0x2);
+        // V3 = key
+        run(&mut c, &[0xF30A], 1);
+        c.input_mut().release(0x2);
+        c.step().unwrap();
+        assert_eq!(c.pc(), 0x200);
+
+        c.input_mut().press(0x7);
+        c.step().unwrap();
+        assert_eq!(c.pc(), 0x200);
+        c.input_mut().release(0x7);
+        c.step().unwrap();
+        assert_eq!(c.pc(), 0x202);
+        assert_eq!(c.registers()[3], 0x7);
+    }
+
+    #[test]
+    fn key_wait_short_tap() {
+        let mut c = chip(None);
+        run(&mut c, &[0xF30A], 1);
+        // Pressed and released between two instructions
+        c.input_mut().press(0xA);
+        c.input_mut().release(0xA);
+        c.step().unwrap();
+        assert_eq!((c.pc(), c.registers()[3]), (0x202, 0xA));
+    }
+
+    #[test]
+    fn key_wait_on_press() {
+        let mut c = chip(None);
+        c.config_mut().key_release_wait = false;
+        run(&mut c, &[0xF30A], 1);
+        assert_eq!(c.pc(), 0x200);
+        c.input_mut().press(0x5);
+        c.step().unwrap();
+        assert_eq!((c.pc(), c.registers()[3]), (0x202, 0x5));
+    }
 }
@@ -1,47 +1,114 @@
-pub mod buzzer;
+pub mod asm;
+pub mod audio;
+#[allow(clippy::module_inception)]
 pub mod chip8;
 pub mod chip_debug;
+pub mod debugger;
+pub mod disasm;
+pub mod error;
+pub mod gdb;
 pub mod input;
 pub mod instruction;
+pub mod movie;
+pub mod profile;
 pub mod renderer;
+pub mod rewind;
+pub mod rng;
+pub mod scheduler;
+pub mod state;
+pub mod timing;
+pub mod trace;
 
-use buzzer::Buzzer;
+use audio::{Audio, Buzzer, HeadlessAudio};
+use debugger::Debugger;
+use error::{Chip8Error, ErrorPolicy};
+use input::{HeadlessInput, Input, SDLInput};
 use instruction::ChipInst;
-use renderer::SDLDisplay;
+use profile::Profile;
+use renderer::{Display, HeadlessDisplay, Renderer, SDLDisplay};
+use rng::Rng;
+use trace::Tracer;
 
 /**
  * Retro-compatibility options
  */
 #[derive(Debug)]
-struct ChipCfg {
-    font_start: u16,       // Starting address of the fonts bytes
-    off_jump_legacy: bool, // If true, BNNN will jump to NNN + V0. Else, to NNN + Vx
-    reg_save_legacy: bool, // If true, FX55 and FX65 will alter the value of I
-    index_add_carry: bool, // If true, carry will be set when I overflows with FX1E
+pub struct ChipCfg {
+    pub font_start: u16,        // Starting address of the fonts bytes
+    pub big_font_start: u16,    // Starting address of the big fonts bytes
+    pub mem_size: usize,        // Size of the RAM, in bytes
+    pub off_jump_legacy: bool,  // If true, BNNN will jump to NNN + V0. Else, to NNN + Vx
+    pub reg_save_legacy: bool,  // If true, FX55 and FX65 will alter the value of I
+    pub index_add_carry: bool,  // If true, carry will be set when I overflows with FX1E
+    pub logic_vf_reset: bool,   // If true, 8XY1, 8XY2 and 8XY3 will set VF to 0
+    pub shift_legacy: bool,     // If true, 8XY6 and 8XYE will shift Vy. Else, Vx
+    pub sprite_wrap: bool,      // If true, DXYN wraps sprites around the screen edges
+    pub display_wait: bool,     // If true, DXYN waits for the next frame
+    pub key_release_wait: bool, // If true, FX0A waits for a key to be pressed then released
+    pub vip_timing: bool,       // If true, instructions take as long as on the COSMAC VIP
+    pub on_error: ErrorPolicy,  // What to do when an instruction fails
+}
+
+/**
+ * @brief Progress of FX0A, waiting for a key.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyWait {
+    Idle,        // FX0A is not being executed
+    Press,       // Waiting for a key to be pressed
+    Release(u8), // Waiting for the pressed key to be released
 }
 
 /**
  * @brief Define the data structure for our Chip8 representation.
+ *
+ * The Chip8 is generic over its peripherals, so that it can be
+ * driven by SDL or by any other backend.
  */
-pub struct Chip8 {
+pub struct Chip8<R: Renderer, A: Audio, I: Input> {
     i: u16,  // 16-bit index register
     pc: u16, // 16-bit program counter
     dt: u8,  // 8-bit delay timer
     st: u8,  // 8-bit sound timer
     sp: u8,  // 8-bit stack pointer
 
-    v: [u8; 16],          // 16 multi-purpose 8-bit registers
-    stack: [u16; 32],     // 32 words deep call-stack
-    mem: [u8; 4096usize], // 4 KiB RAM
+    v: [u8; 16],      // 16 multi-purpose 8-bit registers
+    rpl: [u8; 16],    // SUPER-CHIP RPL user flags
+    stack: [u16; 32], // 32 words deep call-stack
+    mem: Vec<u8>,     // 4 KiB RAM, or 64 KiB for the XO-CHIP
+    program: Vec<u8>, // Last program loaded, loaded again on reset
 
-    disp: SDLDisplay, // The output display
-    audio: Buzzer,    // The audio output
+    pattern: Option<[u8; 16]>, // XO-CHIP audio pattern buffer, unset until F002 or FX3A
+    pitch: u8,                 // XO-CHIP audio pattern pitch
+    rng: Rng,                  // Source of the CXNN random numbers
 
-    config: ChipCfg, // Chip configuration
+    disp: Display, // The framebuffer
 
-    exit: bool, // Boolean set to true if chip should be killed
+    renderer: R, // The output display
+    audio: A,    // The audio output
+    input: I,    // The keypad
+
+    config: ChipCfg,        // Chip configuration
+    debugger: Debugger,     // Breakpoints, watchpoints and run state
+    tracer: Option<Tracer>, // If set, log of the executed instructions
+
+    exit: bool,        // Boolean set to true if chip should be killed
+    vblank_wait: bool, // Boolean set to true if chip waits for the next frame
+    dirty: bool,       // Boolean set to true if the framebuffer changed since presented
+    vip_budget: i32,   // Machine cycles left in the frame, with the VIP timing
+    key_wait: KeyWait, // Progress of FX0A
 }
 
+/**
+ * Chip8 running with the SDL backends.
+ */
+pub type SDLChip8 = Chip8<SDLDisplay, Buzzer, SDLInput>;
+
+/**
+ * Chip8 running without any window, sound device or event pump.
+ */
+pub type HeadlessChip8 = Chip8<HeadlessDisplay, HeadlessAudio, HeadlessInput>;
+
 /**
  * Hexadecimal digits sprites to print on screen.
  */
@@ -63,3 +130,26 @@ const DEFAULT_FONT: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
+
+/**
+ * Big hexadecimal digits sprites, 8x10 pixels,
+ * used by the SUPER-CHIP in high resolution.
+ */
+const BIG_FONT: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
@@ -0,0 +1,278 @@
+use super::{Chip8Error, Profile};
+use std::fs;
+use std::io;
+
+/**
+ * Movies start with this magic, followed by the format version.
+ */
+const MAGIC: &[u8; 4] = b"C8MV";
+const VERSION: u16 = 1;
+
+/**
+ * Flags of the timing options, in the header.
+ */
+const FLAG_VIP_TIMING: u8 = 1 << 0;
+const FLAG_DISPLAY_WAIT_SET: u8 = 1 << 1; // The display wait quirk was overridden
+const FLAG_DISPLAY_WAIT: u8 = 1 << 2; // ... and set
+const FLAGS: u8 = FLAG_VIP_TIMING | FLAG_DISPLAY_WAIT_SET | FLAG_DISPLAY_WAIT;
+
+/**
+ * @brief Keypad state of each frame of a session.
+ *
+ * Along with the seed of the random numbers, the hash of the
+ * program, the platform and the speed of the session, this is
+ * all it takes to play the session again exactly.
+ * Each frame is a bit field, with bit `k` set if the key `k` is held.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub seed: u64,                  // Seed of the random numbers
+    pub rom_hash: u64,              // Hash of the program, as given by `rom_hash`
+    pub profile: Option<Profile>,   // Platform whose quirks were emulated
+    pub cycles: u32,                // Instructions executed per frame
+    pub vip_timing: bool,           // If true, the frames followed the COSMAC VIP timing
+    pub display_wait: Option<bool>, // If set, overrode the display wait quirk of the platform
+    frames: Vec<u16>,               // Keypad state of each frame
+}
+
+/**
+ * @brief Hash of a program, to check that a movie is replayed on it.
+ *
+ * This is the 64 bits FNV-1a hash.
+ */
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xCBF2_9CE4_8422_2325, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+fn invalid(msg: &str) -> Chip8Error {
+    Chip8Error::InvalidMovie(msg.to_string())
+}
+
+/**
+ * Read `N` bytes at `pos`, then move past them.
+ */
+fn take<const N: usize>(data: &[u8], pos: &mut usize) -> Result<[u8; N], Chip8Error> {
+    let bytes = data
+        .get(*pos..*pos + N)
+        .ok_or_else(|| invalid("truncated data"))?;
+    *pos += N;
+    let mut out = [0; N];
+    out.copy_from_slice(bytes);
+    Ok(out)
+}
+
+impl Movie {
+    pub fn new(seed: u64, rom_hash: u64, profile: Option<Profile>, cycles: u32) -> Self {
+        Movie {
+            seed,
+            rom_hash,
+            profile,
+            cycles,
+            vip_timing: false,
+            display_wait: None,
+            frames: Vec::new(),
+        }
+    }
+
+    fn flags(&self) -> u8 {
+        let mut flags = 0;
+        if self.vip_timing {
+            flags |= FLAG_VIP_TIMING;
+        }
+        match self.display_wait {
+            Some(true) => flags |= FLAG_DISPLAY_WAIT_SET | FLAG_DISPLAY_WAIT,
+            Some(false) => flags |= FLAG_DISPLAY_WAIT_SET,
+            None => {}
+        }
+        flags
+    }
+
+    /**
+     * Append the keypad state of the next frame.
+     */
+    pub fn record(&mut self, keys: u16) {
+        self.frames.push(keys);
+    }
+
+    /**
+     * Keypad state of a frame, None past the end of the movie.
+     */
+    pub fn frame(&self, n: usize) -> Option<u16> {
+        self.frames.get(n).copied()
+    }
+
+    pub fn frames(&self) -> &[u16] {
+        &self.frames
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /**
+     * @brief Serialize the movie, in little endian.
+     *
+     * The header holds the seed, the program hash, the number of
+     * instructions per frame, the flags of the timing options and
+     * the name of the platform, empty if none was chosen.
+     * The frames follow, two bytes each.
+     */
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let profile = self.profile.map_or("", |p| p.name());
+        let mut out = Vec::with_capacity(32 + profile.len() + 2 * self.frames.len());
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        out.extend_from_slice(&self.rom_hash.to_le_bytes());
+        out.extend_from_slice(&self.cycles.to_le_bytes());
+        out.push(self.flags());
+        out.push(profile.len() as u8);
+        out.extend_from_slice(profile.as_bytes());
+        out.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        for keys in self.frames.iter() {
+            out.extend_from_slice(&keys.to_le_bytes());
+        }
+        out
+    }
+
+    /**
+     * Read a movie made by `to_bytes`.
+     */
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Chip8Error> {
+        let mut pos = 0;
+        if &take::<4>(data, &mut pos)? != MAGIC {
+            return Err(invalid("not a movie"));
+        }
+        let version = u16::from_le_bytes(take(data, &mut pos)?);
+        if version != VERSION {
+            return Err(Chip8Error::InvalidMovie(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+
+        let seed = u64::from_le_bytes(take(data, &mut pos)?);
+        let rom_hash = u64::from_le_bytes(take(data, &mut pos)?);
+        let cycles = u32::from_le_bytes(take(data, &mut pos)?);
+        let [flags] = take::<1>(data, &mut pos)?;
+        if flags & !FLAGS != 0
+            || flags & (FLAG_DISPLAY_WAIT_SET | FLAG_DISPLAY_WAIT) == FLAG_DISPLAY_WAIT
+        {
+            return Err(Chip8Error::InvalidMovie(format!(
+                "invalid flags {:#04x}",
+                flags
+            )));
+        }
+        let [name_len] = take::<1>(data, &mut pos)?;
+        let name = data
+            .get(pos..pos + name_len as usize)
+            .ok_or_else(|| invalid("truncated data"))?;
+        pos += name_len as usize;
+        let profile = match std::str::from_utf8(name) {
+            Ok("") => None,
+            Ok(name) => Some(name.parse::<Profile>().map_err(Chip8Error::InvalidMovie)?),
+            Err(_) => return Err(invalid("invalid platform name")),
+        };
+
+        let count = u32::from_le_bytes(take(data, &mut pos)?) as usize;
+        if data.len() - pos != 2 * count {
+            return Err(Chip8Error::InvalidMovie(format!(
+                "{} bytes of frames, expected {}",
+                data.len() - pos,
+                2 * count
+            )));
+        }
+        let frames = data[pos..]
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .collect();
+
+        Ok(Movie {
+            seed,
+            rom_hash,
+            profile,
+            cycles,
+            vip_timing: flags & FLAG_VIP_TIMING != 0,
+            display_wait: match flags & (FLAG_DISPLAY_WAIT_SET | FLAG_DISPLAY_WAIT) {
+                0 => None,
+                FLAG_DISPLAY_WAIT_SET => Some(false),
+                _ => Some(true),
+            },
+            frames,
+        })
+    }
+
+    pub fn open(path: &str) -> Result<Self, Chip8Error> {
+        Movie::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rom_hash, Movie, VERSION};
+    use crate::chip8::{Chip8Error, Profile};
+
+    #[test]
+    fn round_trip() {
+        let mut movie = Movie::new(42, rom_hash(b"rom"), Some(Profile::XoChip), 1000);
+        movie.vip_timing = true;
+        movie.display_wait = Some(false);
+        for keys in [0, 1, 0x8000, 0xFFFF] {
+            movie.record(keys);
+        }
+        assert_eq!(Movie::from_bytes(&movie.to_bytes()).unwrap(), movie);
+
+        movie.display_wait = Some(true);
+        assert_eq!(Movie::from_bytes(&movie.to_bytes()).unwrap(), movie);
+
+        let movie = Movie::new(1, 2, None, 3);
+        assert_eq!(Movie::from_bytes(&movie.to_bytes()).unwrap(), movie);
+    }
+
+    #[test]
+    fn rejected() {
+        let mut movie = Movie::new(42, 7, Some(Profile::Vip), 15);
+        movie.record(3);
+        let data = movie.to_bytes();
+        let invalid = |r| matches!(r, Err(Chip8Error::InvalidMovie(_)));
+
+        let mut bad = data.clone();
+        bad[0] = b'X';
+        assert!(invalid(Movie::from_bytes(&bad)));
+
+        for version in [0, VERSION + 1] {
+            let mut bad = data.clone();
+            bad[4..6].copy_from_slice(&version.to_le_bytes());
+            assert!(invalid(Movie::from_bytes(&bad)));
+        }
+
+        for len in 0..data.len() {
+            assert!(invalid(Movie::from_bytes(&data[..len])), "{} bytes", len);
+        }
+
+        let mut bad = data.clone();
+        bad.push(0);
+        assert!(invalid(Movie::from_bytes(&bad)));
+
+        // Unknown flags, and a display wait that was not overridden
+        for flags in [4, 5, 8, 0x80] {
+            let mut bad = data.clone();
+            bad[26] = flags;
+            assert!(invalid(Movie::from_bytes(&bad)));
+        }
+
+        let mut bad = data.clone();
+        bad[28..31].copy_from_slice(b"xyz");
+        assert!(invalid(Movie::from_bytes(&bad)));
+    }
+}
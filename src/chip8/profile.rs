@@ -0,0 +1,111 @@
+use super::ChipCfg;
+use std::fmt;
+use std::str::FromStr;
+
+/**
+ * @brief Well-known Chip8 platforms.
+ *
+ * Programs were written against the quirks of a given
+ * interpreter, a profile selects the matching options.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    Vip,       // Original COSMAC VIP interpreter
+    Chip48,    // CHIP-48 on the HP-48 calculators
+    SuperChip, // SUPER-CHIP 1.1
+    XoChip,    // XO-CHIP, as implemented by Octo
+}
+
+impl Profile {
+    pub const ALL: [Profile; 4] = [
+        Profile::Vip,
+        Profile::Chip48,
+        Profile::SuperChip,
+        Profile::XoChip,
+    ];
+
+    /**
+     * Name of the profile, as accepted on the command line.
+     */
+    pub fn name(&self) -> &'static str {
+        match self {
+            Profile::Vip => "vip",
+            Profile::Chip48 => "chip48",
+            Profile::SuperChip => "schip",
+            Profile::XoChip => "xochip",
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Profile::ALL
+            .iter()
+            .find(|p| p.name() == s.to_lowercase())
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<&str> = Profile::ALL.iter().map(|p| p.name()).collect();
+                format!(
+                    "Unknown profile '{}', expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+impl ChipCfg {
+    /**
+     * @brief Build the options matching the given platform.
+     */
+    pub fn from_profile(profile: Profile) -> Self {
+        let base = ChipCfg::default();
+        match profile {
+            Profile::Vip => ChipCfg {
+                off_jump_legacy: true,
+                reg_save_legacy: true,
+                logic_vf_reset: true,
+                shift_legacy: true,
+                sprite_wrap: false,
+                display_wait: true,
+                ..base
+            },
+            Profile::Chip48 => ChipCfg {
+                off_jump_legacy: false,
+                reg_save_legacy: true,
+                logic_vf_reset: false,
+                shift_legacy: false,
+                sprite_wrap: false,
+                display_wait: false,
+                ..base
+            },
+            Profile::SuperChip => ChipCfg {
+                off_jump_legacy: false,
+                reg_save_legacy: false,
+                logic_vf_reset: false,
+                shift_legacy: false,
+                sprite_wrap: false,
+                display_wait: false,
+                ..base
+            },
+            Profile::XoChip => ChipCfg {
+                off_jump_legacy: true,
+                reg_save_legacy: true,
+                logic_vf_reset: false,
+                shift_legacy: true,
+                sprite_wrap: true,
+                display_wait: false,
+                mem_size: 0x10000,
+                ..base
+            },
+        }
+    }
+}
@@ -1,53 +1,242 @@
+use std::fmt;
+
 /**
  * Simple Structure allowing to munipulate pixels
- * of a 64x32 screen.
+ * of a 64x32 screen, or 128x64 in high resolution mode.
  *
- * Because the screen is only 64 pixels large, we can
- * use u64 types to hold all the bits for a line.
+ * Because the screen is at most 128 pixels large, we can
+ * use u128 types to hold all the bits for a line.
  * This approach prevents from memory waste that the
  * '1 char per pixel' method would have produced.
+ *
+ * The pixel (x, y) is the bit (127 - x) of planes[p][y],
+ * in low resolution only the upper 64 bits of the
+ * first 32 lines are used.
+ * There are two bitplanes, as on the XO-CHIP, so each pixel
+ * has one of four colours. Clearing and scrolling only
+ * apply to the selected planes.
  */
 pub struct Display {
-    pub tab: [u64; 32],
+    pub planes: [[u128; 64]; 2],
+    hires: bool,
+    selected: u8,
 }
 
 impl Display {
     pub fn new() -> Display {
-        Display { tab: [0; 32] }
+        Display {
+            planes: [[0; 64]; 2],
+            hires: false,
+            selected: 0b01,
+        }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /**
+     * Switch between 64x32 and 128x64 modes, clearing the screen.
+     */
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.planes = [[0; 64]; 2];
+    }
+
+    /**
+     * Bitmask of the planes drawn on, bit 0 being the first plane.
+     */
+    pub fn selected_planes(&self) -> u8 {
+        self.selected
+    }
+
+    pub fn select_planes(&mut self, mask: u8) {
+        self.selected = mask & 0b11;
+    }
+
+    /**
+     * Indexes of the selected planes.
+     */
+    pub fn selected_iter(&self) -> impl Iterator<Item = usize> {
+        let selected = self.selected;
+        (0..2).filter(move |p| selected & (1 << p) != 0)
+    }
+
+    pub fn width(&self) -> u8 {
+        if self.hires {
+            128
+        } else {
+            64
+        }
+    }
+
+    pub fn height(&self) -> u8 {
+        if self.hires {
+            64
+        } else {
+            32
+        }
+    }
+
+    /**
+     * Bits of a line which are on screen.
+     */
+    fn line_mask(&self) -> u128 {
+        !0u128 << (128 - self.width() as u32)
+    }
+
+    /**
+     * Colour of the pixel (x, y), from 0 to 3.
+     * Bit 0 is set if lit on the first plane, bit 1 for the second.
+     */
+    pub fn color(&self, x: u8, y: u8) -> u8 {
+        let x = x % self.width();
+        let y = y % self.height();
+        let bit = 1 << (127 - x);
+        let lit = |p: usize| (self.planes[p][y as usize] & bit != 0) as u8;
+        lit(0) | (lit(1) << 1)
+    }
+
+    /**
+     * Return true if the pixel (x, y) is lit on any plane.
+     */
+    pub fn pixel(&self, x: u8, y: u8) -> bool {
+        self.color(x, y) != 0
     }
 
     /**
-     * Set all the pixels to 0
+     * Set all the pixels of the selected planes to 0
      */
     pub fn clear(&mut self) {
-        for i in self.tab.iter_mut() {
-            *i = 0u64;
+        for p in self.selected_iter() {
+            self.planes[p] = [0; 64];
         }
     }
 
     /**
-     * Apply a byte to the screen.
+     * Apply a byte to the screen, on the given plane.
      *
-     * The bit 0 of the given byte is applied on
-     * the pixel (x, y), the bit 1 on (x + 1, y), etc...
+     * The bit 7 of the given byte is applied on
+     * the pixel (x, y), the bit 6 on (x + 1, y), etc...
      * 'Applying' the sprite only does a XOR between the
      * actual value and the byte value.
+     *
+     * Pixels going past the right edge are either dropped,
+     * or drawn on the left side if `wrap` is set.
+     * Return true if any lit pixel has been turned off.
+     */
+    pub fn draw_sprite(&mut self, plane: usize, x: u8, y: u8, byte: u8, wrap: bool) -> bool {
+        self.draw_line(plane, x, y, (byte as u128) << 120, wrap)
+    }
+
+    /**
+     * Apply a 16 pixels large row to the screen,
+     * the same way `draw_sprite` does.
      */
-    pub fn draw_sprite(&mut self, x: u8, y: u8, byte: u8) {
+    pub fn draw_sprite16(&mut self, plane: usize, x: u8, y: u8, word: u16, wrap: bool) -> bool {
+        self.draw_line(plane, x, y, (word as u128) << 112, wrap)
+    }
+
+    fn draw_line(&mut self, plane: usize, x: u8, y: u8, sprite: u128, wrap: bool) -> bool {
         // Get the 'line' to update
-        let mut val: u64 = self.tab[y as usize];
+        let mut val: u128 = self.planes[plane][y as usize];
 
-        // Compute the mask, thanks to bit shifting
-        let mask = if x > 56 {
-            (byte as u64) >> (x - 56)
-        } else {
-            (byte as u64) << (56 - x)
-        };
+        // Compute the mask, thanks to bit shifting.
+        // Pixels past the right edge are moved back
+        // by the width of the screen when wrapping
+        let x = (x % self.width()) as u32;
+        let mut mask = sprite >> x;
+        if wrap {
+            let back = self.width() as u32 - x;
+            mask |= sprite.checked_shl(back).unwrap_or(0);
+        }
+        mask &= self.line_mask();
 
-        // Apply the mask
+        // Check for collision, then apply the mask
+        let collision = val & mask != 0;
         val ^= mask;
 
         // Update the array
-        self.tab[y as usize] = val;
+        self.planes[plane][y as usize] = val;
+        collision
+    }
+
+    /**
+     * Move the content of the screen `n` pixels down.
+     */
+    pub fn scroll_down(&mut self, n: u8) {
+        let h = self.height() as usize;
+        let n = (n as usize).min(h);
+        for p in self.selected_iter() {
+            let tab = &mut self.planes[p];
+            tab.copy_within(0..h - n, n);
+            for line in tab[..n].iter_mut() {
+                *line = 0;
+            }
+        }
+    }
+
+    /**
+     * Move the content of the screen `n` pixels up.
+     */
+    pub fn scroll_up(&mut self, n: u8) {
+        let h = self.height() as usize;
+        let n = (n as usize).min(h);
+        for p in self.selected_iter() {
+            let tab = &mut self.planes[p];
+            tab.copy_within(n..h, 0);
+            for line in tab[h - n..h].iter_mut() {
+                *line = 0;
+            }
+        }
+    }
+
+    /**
+     * Move the content of the screen `n` pixels right.
+     */
+    pub fn scroll_right(&mut self, n: u8) {
+        let mask = self.line_mask();
+        let h = self.height() as usize;
+        for p in self.selected_iter() {
+            for line in self.planes[p][..h].iter_mut() {
+                *line = (*line >> n) & mask;
+            }
+        }
+    }
+
+    /**
+     * Move the content of the screen `n` pixels left.
+     */
+    pub fn scroll_left(&mut self, n: u8) {
+        let h = self.height() as usize;
+        for p in self.selected_iter() {
+            for line in self.planes[p][..h].iter_mut() {
+                *line <<= n;
+            }
+        }
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/**
+ * Draw the screen with ascii characters, one line per row.
+ * Pixels lit only on the second plane are shown with 'o',
+ * and with '@' if lit on both.
+ */
+impl fmt::Display for Display {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const CHARS: [char; 4] = ['.', '#', 'o', '@'];
+        for y in 0..self.height() {
+            let line: String = (0..self.width())
+                .map(|x| CHARS[self.color(x, y) as usize])
+                .collect();
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,37 @@
+use super::{Display, Renderer};
+
+/**
+ * @brief Renderer that does not show anything.
+ *
+ * The framebuffer stays available through the Chip8,
+ * which is enough to run programs without a window.
+ */
+pub struct HeadlessDisplay {
+    frames: u64,
+}
+
+impl HeadlessDisplay {
+    pub fn new() -> Self {
+        HeadlessDisplay { frames: 0 }
+    }
+
+    /**
+     * Number of times the screen would have been presented.
+     */
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+}
+
+impl Default for HeadlessDisplay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Renderer for HeadlessDisplay {
+    fn render(&mut self, _disp: &Display) -> Result<(), String> {
+        self.frames += 1;
+        Ok(())
+    }
+}
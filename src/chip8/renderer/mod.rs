@@ -1,8 +1,22 @@
 // Exported modules
+pub mod display;
+pub mod headless;
 pub mod sdl_display;
 
 // Avoid verbosity
+pub use display::Display;
+pub use headless::HeadlessDisplay;
 pub use sdl_display::SDLDisplay;
 
-// INTERNAL USE
-mod display;
+/**
+ * @brief Output device showing the content of the Chip8's screen.
+ *
+ * The framebuffer itself is owned by the Chip8, a renderer
+ * only has to present it when asked to.
+ */
+pub trait Renderer {
+    /**
+     * Present the given framebuffer.
+     */
+    fn render(&mut self, disp: &Display) -> Result<(), String>;
+}
@@ -1,12 +1,21 @@
-use super::display::Display;
+use super::{Display, Renderer};
 
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use sdl2::render::WindowCanvas;
 use sdl2::video::Window;
 
+/**
+ * Colours of the pixels, indexed by the planes they are lit on.
+ */
+const PALETTE: [Color; 4] = [
+    Color::BLACK,
+    Color::WHITE,
+    Color::RGB(0xFF, 0x66, 0x00),
+    Color::RGB(0x66, 0x22, 0x00),
+];
+
 pub struct SDLDisplay {
-    disp: Display,
     canvas: WindowCanvas,
 }
 
@@ -19,57 +28,49 @@ impl SDLDisplay {
         canvas.clear();
         canvas.present();
 
-        Ok(SDLDisplay {
-            disp: Display::new(),
-            canvas,
-        })
+        Ok(SDLDisplay { canvas })
     }
-    pub fn render(&mut self) {
-        let size = self.canvas.output_size();
-        if let Err(e) = &size {
-            panic!("Panic on size: {}", e);
-        }
 
-        let (width, height) = size.unwrap();
-        let pt_width = width / 64;
-        let pt_height = height / 32;
+    pub fn set_title(&mut self, title: &str) {
+        // Titles are built by the frontend, they hold no NUL byte
+        let _ = self.canvas.window_mut().set_title(title);
+    }
+}
+
+impl Renderer for SDLDisplay {
+    fn render(&mut self, disp: &Display) -> Result<(), String> {
+        let (width, height) = self.canvas.output_size()?;
+        let (cols, rows) = (disp.width(), disp.height());
+        let pt_width = width / cols as u32;
+        let pt_height = height / rows as u32;
 
-        let mut pixel;
+        let mut color;
         let mut rect: Rect;
 
         // First, clear the screen
-        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.set_draw_color(PALETTE[0]);
         self.canvas.clear();
-        self.canvas.set_draw_color(Color::WHITE);
 
-        for row in 0..32 {
-            for col in 0..64 {
-                // Pixel is true only if (row, col) is 1
-                pixel = self.disp.tab[row as usize] & (1 << (63 - col)) != 0;
+        for row in 0..rows {
+            for col in 0..cols {
+                // Color is 0 only if (row, col) is off on all planes
+                color = disp.color(col, row);
 
-                // Only draw if white
-                if pixel {
+                // Only draw if not background
+                if color != 0 {
+                    self.canvas.set_draw_color(PALETTE[color as usize]);
                     rect = Rect::from((
-                        col * pt_width as i32,
-                        row * pt_height as i32,
+                        col as i32 * pt_width as i32,
+                        row as i32 * pt_height as i32,
                         pt_width,
                         pt_height,
                     ));
-                    if let Err(e) = self.canvas.fill_rect(rect) {
-                        panic!("Error on fill rect: {}", e);
-                    }
+                    self.canvas.fill_rect(rect)?;
                 }
             }
         }
 
         self.canvas.present();
-    }
-
-    pub fn clear(&mut self) {
-        self.disp.clear();
-    }
-
-    pub fn draw_sprite(&mut self, x: u8, y: u8, byte: u8) {
-        self.disp.draw_sprite(x, y, byte)
+        Ok(())
     }
 }
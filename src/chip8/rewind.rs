@@ -0,0 +1,236 @@
+use std::collections::VecDeque;
+
+/**
+ * @brief Ring buffer of the last states of a chip, to run time backwards.
+ *
+ * Only the newest state is kept whole. Each older one is stored
+ * as the difference with the state following it, XORed then
+ * run-length encoded, so that a frame which changed a few bytes
+ * only takes a few bytes.
+ * States are the ones made by `Chip8::save_state`.
+ */
+pub struct Rewind {
+    capacity: usize,           // Number of older states kept
+    last: Option<Vec<u8>>,     // Newest state
+    deltas: VecDeque<Vec<u8>>, // Deltas to the older states, the newest last
+}
+
+/**
+ * Append `n` as a LEB128 variable length integer.
+ */
+fn put_varint(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn get_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut n = 0;
+    let mut shift = 0;
+    while let Some(&b) = data.get(*pos) {
+        *pos += 1;
+        n |= ((b & 0x7F) as usize) << shift;
+        if b & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    n
+}
+
+/**
+ * @brief Encode the difference turning `from` into `to`.
+ *
+ * The delta is the length of `to`, then runs of unchanged
+ * bytes followed by runs of XORed bytes.
+ */
+fn encode(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let xor = |k: usize| to.get(k).copied().unwrap_or(0) ^ from.get(k).copied().unwrap_or(0);
+    let len = from.len().max(to.len());
+    let mut out = Vec::new();
+    put_varint(&mut out, to.len());
+
+    let mut k = 0;
+    while k < len {
+        let start = k;
+        while k < len && xor(k) == 0 {
+            k += 1;
+        }
+        put_varint(&mut out, k - start);
+
+        let start = k;
+        while k < len && xor(k) != 0 {
+            k += 1;
+        }
+        put_varint(&mut out, k - start);
+        out.extend((start..k).map(xor));
+    }
+    out
+}
+
+/**
+ * Apply a delta made by `encode` to `from`.
+ */
+fn decode(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let len = get_varint(delta, &mut pos);
+    let mut out = from.to_vec();
+    out.resize(from.len().max(len), 0);
+
+    let mut k = 0;
+    while pos < delta.len() {
+        k += get_varint(delta, &mut pos);
+        let changed = get_varint(delta, &mut pos);
+        for (byte, x) in out[k..k + changed]
+            .iter_mut()
+            .zip(&delta[pos..pos + changed])
+        {
+            *byte ^= x;
+        }
+        pos += changed;
+        k += changed;
+    }
+    out.truncate(len);
+    out
+}
+
+impl Rewind {
+    /**
+     * Create a buffer able to go back `capacity` states.
+     */
+    pub fn new(capacity: usize) -> Self {
+        Rewind {
+            capacity,
+            last: None,
+            deltas: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /**
+     * Record the newest state, dropping the oldest one if full.
+     */
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(last) = self.last.take() {
+            if self.deltas.len() == self.capacity {
+                self.deltas.pop_front();
+            }
+            if self.capacity > 0 {
+                self.deltas.push_back(encode(&state, &last));
+            }
+        }
+        self.last = Some(state);
+    }
+
+    /**
+     * @brief Go back one state.
+     *
+     * Drop the newest state, and return the one before it.
+     */
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let delta = self.deltas.pop_back()?;
+        let state = decode(self.last.as_deref().unwrap_or(&[]), &delta);
+        self.last = Some(state.clone());
+        Some(state)
+    }
+
+    /**
+     * Number of states which can be gone back to.
+     */
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /**
+     * Size of the recorded states, in bytes.
+     */
+    pub fn size(&self) -> usize {
+        let last = self.last.as_ref().map_or(0, |s| s.len());
+        last + self.deltas.iter().map(|d| d.len()).sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.last = None;
+        self.deltas.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rewind;
+    use crate::chip8::HeadlessChip8;
+
+    #[test]
+    fn delta_round_trip() {
+        let mut rewind = Rewind::new(4);
+        let states = [
+            vec![1, 2, 3, 4],
+            vec![1, 2, 9, 4, 5],
+            vec![7],
+            vec![7, 0, 0, 1],
+        ];
+        for state in states.iter() {
+            rewind.push(state.clone());
+        }
+        assert_eq!(rewind.len(), 3);
+        for state in states[..3].iter().rev() {
+            assert_eq!(rewind.pop().as_ref(), Some(state));
+        }
+        assert!(rewind.pop().is_none());
+    }
+
+    #[test]
+    fn rewinding_keeps_the_tone() {
+        let mut chip = HeadlessChip8::new_headless();
+        // V0 = 30; ST = V0; loop
+        chip.load_program(&[0x60, 0x1E, 0xF0, 0x18, 0x12, 0x04])
+            .unwrap();
+        let mut rewind = Rewind::new(8);
+        for _ in 0..4 {
+            chip.run_frame(10).unwrap();
+            rewind.push(chip.save_state());
+        }
+        let state = rewind.pop().unwrap();
+        chip.load_state(&state).unwrap();
+        assert!(chip.sound_timer() > 0);
+        assert!(chip.audio().pattern().is_none());
+    }
+
+    #[test]
+    fn rewinding_keeps_the_pattern() {
+        let mut chip = HeadlessChip8::new_headless();
+        // I = pattern; audio; V0 = 80; pitch = V0; loop; pattern
+        let mut rom = vec![0xA2, 0x0A, 0xF0, 0x02, 0x60, 0x50, 0xF0, 0x3A, 0x12, 0x08];
+        rom.extend_from_slice(&[0xF0; 16]);
+        chip.load_program(&rom).unwrap();
+        let mut rewind = Rewind::new(8);
+        for _ in 0..2 {
+            chip.run_frame(10).unwrap();
+            rewind.push(chip.save_state());
+        }
+        chip.load_state(&rewind.pop().unwrap()).unwrap();
+        assert_eq!(chip.audio().pattern(), Some(&[0xF0; 16]));
+    }
+
+    #[test]
+    fn pitch_alone_keeps_the_tone() {
+        let mut chip = HeadlessChip8::new_headless();
+        // V0 = 80; pitch = V0; V1 = 30; ST = V1; loop
+        chip.load_program(&[0x60, 0x50, 0xF0, 0x3A, 0x61, 0x1E, 0xF1, 0x18, 0x12, 0x08])
+            .unwrap();
+        let mut rewind = Rewind::new(8);
+        for _ in 0..2 {
+            chip.run_frame(10).unwrap();
+            rewind.push(chip.save_state());
+        }
+        assert!(chip.audio().is_playing());
+        assert!(chip.audio().pattern().is_none());
+        chip.load_state(&rewind.pop().unwrap()).unwrap();
+        assert!(chip.audio().pattern().is_none());
+    }
+}
@@ -0,0 +1,90 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+/**
+ * Frames the scheduler may fall behind before dropping them.
+ */
+const MAX_LAG: u32 = 5;
+
+/**
+ * @brief Pace the frames at a fixed rate.
+ *
+ * The deadline of each frame follows the one of the previous
+ * frame, not the time at which it ended, so the time spent running
+ * a frame and the oversleeping of the host do not accumulate.
+ * A frame which ends late makes the next waits shorter, until the
+ * schedule is caught up. If the host falls more than a few frames
+ * behind, the schedule restarts from now instead of running the
+ * late frames in a burst.
+ */
+pub struct FrameScheduler {
+    period: Duration, // Time between two frames
+    next: Instant,    // Deadline of the current frame
+}
+
+impl FrameScheduler {
+    /**
+     * Rate of the frames of the Chip8, and of its timers.
+     */
+    pub const FRAME_RATE: u32 = 60;
+
+    /**
+     * Create a scheduler of `rate` frames per second, starting now.
+     */
+    pub fn new(rate: u32) -> Self {
+        let period = Duration::from_secs(1) / rate.max(1);
+        FrameScheduler {
+            period,
+            next: Instant::now() + period,
+        }
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /**
+     * Change the rate of the frames, from the next one.
+     */
+    pub fn set_rate(&mut self, rate: u32) {
+        let period = Duration::from_secs(1) / rate.max(1);
+        self.next = self.next - self.period + period;
+        self.period = period;
+    }
+
+    /**
+     * @brief Sleep until the end of the current frame.
+     *
+     * Return the time slept.
+     */
+    pub fn wait(&mut self) -> Duration {
+        let now = Instant::now();
+        let slept = match self.next.checked_duration_since(now) {
+            Some(left) => {
+                thread::sleep(left);
+                left
+            }
+            None => {
+                if now - self.next > self.period * MAX_LAG {
+                    self.next = now;
+                }
+                Duration::ZERO
+            }
+        };
+        self.next += self.period;
+        slept
+    }
+
+    /**
+     * Restart the schedule from now, such as after a pause.
+     */
+    pub fn reset(&mut self) {
+        self.next = Instant::now() + self.period;
+    }
+}
+
+impl Default for FrameScheduler {
+    fn default() -> Self {
+        FrameScheduler::new(FrameScheduler::FRAME_RATE)
+    }
+}
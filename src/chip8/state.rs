@@ -0,0 +1,350 @@
+use super::{Audio, Chip8, Chip8Error, ChipCfg, ErrorPolicy, Input, KeyWait, Renderer};
+
+/**
+ * Save states start with this magic, followed by the format version.
+ */
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u16 = 1;
+
+/**
+ * @brief Reader over the bytes of a save state.
+ */
+struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], Chip8Error> {
+        let end = self.pos + n;
+        if end > self.data.len() {
+            return Err(Chip8Error::InvalidState("truncated data".to_string()));
+        }
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Chip8Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, Chip8Error> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn u16(&mut self) -> Result<u16, Chip8Error> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Chip8Error> {
+        let mut b = [0; 4];
+        b.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn u64(&mut self) -> Result<u64, Chip8Error> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+
+    fn u128(&mut self) -> Result<u128, Chip8Error> {
+        let mut b = [0; 16];
+        b.copy_from_slice(self.bytes(16)?);
+        Ok(u128::from_le_bytes(b))
+    }
+}
+
+/**
+ * Encode the progress of FX0A as its state, then its key.
+ */
+fn key_wait_bytes(wait: KeyWait) -> [u8; 2] {
+    match wait {
+        KeyWait::Idle => [0, 0],
+        KeyWait::Press => [1, 0],
+        KeyWait::Release(key) => [2, key],
+    }
+}
+
+fn key_wait_from_bytes(b: &[u8]) -> Result<KeyWait, Chip8Error> {
+    match (b[0], b[1]) {
+        (0, _) => Ok(KeyWait::Idle),
+        (1, _) => Ok(KeyWait::Press),
+        (2, key) if key < 16 => Ok(KeyWait::Release(key)),
+        _ => Err(Chip8Error::InvalidState(format!(
+            "unknown key wait {}, {}",
+            b[0], b[1]
+        ))),
+    }
+}
+
+fn policy_id(policy: ErrorPolicy) -> u8 {
+    match policy {
+        ErrorPolicy::Halt => 0,
+        ErrorPolicy::Skip => 1,
+        ErrorPolicy::Ignore => 2,
+    }
+}
+
+fn policy_from_id(id: u8) -> Result<ErrorPolicy, Chip8Error> {
+    match id {
+        0 => Ok(ErrorPolicy::Halt),
+        1 => Ok(ErrorPolicy::Skip),
+        2 => Ok(ErrorPolicy::Ignore),
+        _ => Err(Chip8Error::InvalidState(format!(
+            "unknown error policy {}",
+            id
+        ))),
+    }
+}
+
+impl<R: Renderer, A: Audio, I: Input> Chip8<R, A, I> {
+    /**
+     * @brief Serialize the whole machine.
+     *
+     * The state holds the registers, the stack, the memory, the
+     * timers, the framebuffer, the configuration and the state of
+     * the random number generator, in little endian.
+     * It can be written to a file, and given back to `load_state`.
+     */
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.mem.len() + 0x1000);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+
+        // Registers and stack
+        out.extend_from_slice(&self.pc.to_le_bytes());
+        out.extend_from_slice(&self.i.to_le_bytes());
+        out.extend_from_slice(&[self.sp, self.dt, self.st]);
+        out.extend_from_slice(&self.v);
+        out.extend_from_slice(&self.rpl);
+        for addr in self.stack.iter() {
+            out.extend_from_slice(&addr.to_le_bytes());
+        }
+        out.push(self.pattern.is_some() as u8);
+        out.extend_from_slice(&self.pattern.unwrap_or([0; 16]));
+        out.extend_from_slice(&[self.pitch, self.exit as u8, self.vblank_wait as u8]);
+        out.extend_from_slice(&self.rng.state().to_le_bytes());
+        out.extend_from_slice(&key_wait_bytes(self.key_wait));
+        out.extend_from_slice(&self.vip_budget.to_le_bytes());
+
+        // Configuration
+        let cfg = &self.config;
+        out.extend_from_slice(&cfg.font_start.to_le_bytes());
+        out.extend_from_slice(&cfg.big_font_start.to_le_bytes());
+        out.extend_from_slice(&(cfg.mem_size as u32).to_le_bytes());
+        out.extend_from_slice(&[
+            cfg.off_jump_legacy as u8,
+            cfg.reg_save_legacy as u8,
+            cfg.index_add_carry as u8,
+            cfg.logic_vf_reset as u8,
+            cfg.shift_legacy as u8,
+            cfg.sprite_wrap as u8,
+            cfg.display_wait as u8,
+            cfg.key_release_wait as u8,
+            cfg.vip_timing as u8,
+            policy_id(cfg.on_error),
+        ]);
+
+        // Framebuffer
+        out.extend_from_slice(&[self.disp.is_hires() as u8, self.disp.selected_planes()]);
+        for plane in self.disp.planes.iter() {
+            for line in plane.iter() {
+                out.extend_from_slice(&line.to_le_bytes());
+            }
+        }
+
+        // Memory
+        out.extend_from_slice(&(self.mem.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.mem);
+        out
+    }
+
+    /**
+     * @brief Restore the machine from a state made by `save_state`.
+     *
+     * The chip is left untouched if the state is invalid.
+     */
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), Chip8Error> {
+        let mut r = StateReader { data, pos: 0 };
+        if r.bytes(4)? != MAGIC {
+            return Err(Chip8Error::InvalidState("not a save state".to_string()));
+        }
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(Chip8Error::InvalidState(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+
+        let pc = r.u16()?;
+        let i = r.u16()?;
+        let (sp, dt, st) = (r.u8()?, r.u8()?, r.u8()?);
+        let mut v = [0; 16];
+        v.copy_from_slice(r.bytes(16)?);
+        let mut rpl = [0; 16];
+        rpl.copy_from_slice(r.bytes(16)?);
+        let mut stack = [0; 32];
+        for addr in stack.iter_mut() {
+            *addr = r.u16()?;
+        }
+        if sp as usize >= stack.len() {
+            return Err(Chip8Error::InvalidState(format!("stack pointer {}", sp)));
+        }
+        let pattern_set = r.bool()?;
+        let mut pattern = [0; 16];
+        pattern.copy_from_slice(r.bytes(16)?);
+        let pattern = if pattern_set { Some(pattern) } else { None };
+        let (pitch, exit, vblank_wait) = (r.u8()?, r.bool()?, r.bool()?);
+        let rng = r.u64()?;
+        let key_wait = key_wait_from_bytes(r.bytes(2)?)?;
+        let vip_budget = r.u32()? as i32;
+
+        let config = ChipCfg {
+            font_start: r.u16()?,
+            big_font_start: r.u16()?,
+            mem_size: r.u32()? as usize,
+            off_jump_legacy: r.bool()?,
+            reg_save_legacy: r.bool()?,
+            index_add_carry: r.bool()?,
+            logic_vf_reset: r.bool()?,
+            shift_legacy: r.bool()?,
+            sprite_wrap: r.bool()?,
+            display_wait: r.bool()?,
+            key_release_wait: r.bool()?,
+            vip_timing: r.bool()?,
+            on_error: policy_from_id(r.u8()?)?,
+        };
+
+        let (hires, selected) = (r.bool()?, r.u8()?);
+        let mut planes = [[0; 64]; 2];
+        for plane in planes.iter_mut() {
+            for line in plane.iter_mut() {
+                *line = r.u128()?;
+            }
+        }
+
+        let mem_len = r.u32()? as usize;
+        if mem_len != config.mem_size {
+            return Err(Chip8Error::InvalidState(format!(
+                "memory of {} bytes, expected {}",
+                mem_len, config.mem_size
+            )));
+        }
+        let mem = r.bytes(mem_len)?.to_vec();
+
+        // Everything has been read, the chip can be changed
+        self.pc = pc;
+        self.i = i;
+        self.sp = sp;
+        self.dt = dt;
+        self.st = st;
+        self.v = v;
+        self.rpl = rpl;
+        self.stack = stack;
+        self.pattern = pattern;
+        self.pitch = pitch;
+        self.exit = exit;
+        self.vblank_wait = vblank_wait;
+        self.rng.set_state(rng);
+        self.key_wait = key_wait;
+        self.vip_budget = vip_budget;
+        self.config = config;
+        self.disp.set_hires(hires);
+        self.disp.select_planes(selected);
+        self.disp.planes = planes;
+        self.mem = mem;
+
+        self.refresh_pattern();
+        self.refresh_buzzer();
+        self.present()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyWait, VERSION};
+    use crate::chip8::{Chip8Error, ErrorPolicy, HeadlessChip8};
+
+    fn program() -> HeadlessChip8 {
+        let mut chip = HeadlessChip8::new_headless();
+        chip.set_seed(42);
+        // V0 = random; V1 = 30; ST = V1; I = 0x300; draw; call; loop
+        chip.load_program(&[
+            0xC0, 0xFF, 0x61, 0x1E, 0xF1, 0x18, 0xA3, 0x00, 0xD0, 0x15, 0x22, 0x0E, 0x12, 0x0C,
+            0x12, 0x0E,
+        ])
+        .unwrap();
+        chip.run_frame(20).unwrap();
+        chip
+    }
+
+    #[test]
+    fn round_trip() {
+        let chip = program();
+        let state = chip.save_state();
+
+        let mut other = HeadlessChip8::new_headless();
+        other.load_state(&state).unwrap();
+        assert_eq!(other.save_state(), state);
+        assert_eq!(other.pc(), chip.pc());
+        assert_eq!(other.registers(), chip.registers());
+        assert_eq!(other.call_stack(), chip.call_stack());
+        assert_eq!(other.memory(), chip.memory());
+        assert_eq!(other.framebuffer().planes, chip.framebuffer().planes);
+        assert!(other.audio().is_playing());
+    }
+
+    #[test]
+    fn round_trip_of_the_hidden_state() {
+        let mut chip = program();
+        chip.pattern = Some([0xA5; 16]);
+        chip.pitch = 80;
+        chip.key_wait = KeyWait::Release(5);
+        chip.vip_budget = -12;
+        chip.config.key_release_wait = false;
+        chip.config.vip_timing = true;
+        chip.config.sprite_wrap = true;
+        chip.config.on_error = ErrorPolicy::Skip;
+        let state = chip.save_state();
+
+        let mut other = HeadlessChip8::new_headless();
+        other.load_state(&state).unwrap();
+        assert_eq!(other.pattern, chip.pattern);
+        assert_eq!(other.audio().pattern(), Some(&[0xA5; 16]));
+        assert_eq!(other.pitch, 80);
+        assert_eq!(other.key_wait, KeyWait::Release(5));
+        assert_eq!(other.vip_budget, -12);
+        assert_eq!(other.rng.state(), chip.rng.state());
+        let cfg = other.config();
+        assert!(!cfg.key_release_wait && cfg.vip_timing && cfg.sprite_wrap);
+        assert_eq!(cfg.on_error, ErrorPolicy::Skip);
+    }
+
+    #[test]
+    fn rejected() {
+        let state = program().save_state();
+        let mut chip = HeadlessChip8::new_headless();
+        let invalid = |r| matches!(r, Err(Chip8Error::InvalidState(_)));
+
+        let mut bad = state.clone();
+        bad[0] = b'X';
+        assert!(invalid(chip.load_state(&bad)));
+
+        for version in [0, VERSION + 1] {
+            let mut bad = state.clone();
+            bad[4..6].copy_from_slice(&version.to_le_bytes());
+            assert!(invalid(chip.load_state(&bad)));
+        }
+
+        for len in 0..state.len() {
+            assert!(invalid(chip.load_state(&state[..len])), "{} bytes", len);
+        }
+
+        // Nothing was loaded
+        assert_eq!(chip.pc(), 0x200);
+    }
+}
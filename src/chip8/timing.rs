@@ -0,0 +1,145 @@
+use super::{Audio, Chip8, ChipInst, Input, Renderer};
+
+/**
+ * The CDP1802 of the COSMAC VIP runs at 1.7609 MHz, and takes
+ * 8 clock pulses per machine cycle: about 3668 machine cycles
+ * happen during each 60Hz frame.
+ */
+pub const VIP_FRAME_CYCLES: i32 = 3668;
+
+/**
+ * Machine cycles of each frame taken by the display interrupt,
+ * and by the DMA feeding the 1861 with the 256 bytes of the screen.
+ */
+pub const VIP_INTERRUPT_CYCLES: i32 = 1100;
+
+/**
+ * Machine cycles taken by the interpreter to fetch and decode
+ * an instruction, before running its routine.
+ */
+const VIP_FETCH_CYCLES: u32 = 40;
+
+impl<R: Renderer, A: Audio, I: Input> Chip8<R, A, I> {
+    /**
+     * @brief Cost of an instruction on the COSMAC VIP, in machine cycles.
+     *
+     * The costs follow the routines of the original interpreter,
+     * rounded to the cycle. They are computed before the instruction
+     * runs, as skips, sprite alignment and register counts change them.
+     * Instructions which the VIP did not have cost the same as a
+     * simple one.
+     */
+    pub(super) fn vip_cost(&self, inst: &ChipInst) -> u32 {
+        let vx = self.v[inst.x as usize];
+        let vy = self.v[inst.y as usize];
+        // Skips take a few more cycles to step over the next instruction
+        let skip = |taken: bool| if taken { 4 } else { 0 };
+
+        let routine = match (inst.i, inst.nn) {
+            (0x0, 0xE0) => 3078,
+            (0x0, 0xEE) => 10,
+            (0x1, _) => 12,
+            (0x2, _) => 26,
+            (0x3, nn) => 10 + skip(vx == nn),
+            (0x4, nn) => 10 + skip(vx != nn),
+            (0x5, _) => 14 + skip(vx == vy),
+            (0x6, _) => 6,
+            (0x7, _) => 10,
+            (0x8, _) => 44,
+            (0x9, _) => 14 + skip(vx != vy),
+            (0xA, _) => 12,
+            (0xB, _) => 22,
+            (0xC, _) => 36,
+            (0xD, _) => self.vip_draw_cost(inst),
+            (0xE, 0x9E) => 14 + skip(self.input.is_pressed(vx)),
+            (0xE, 0xA1) => 14 + skip(!self.input.is_pressed(vx)),
+            (0xF, 0x07) => 10,
+            (0xF, 0x0A) => 19,
+            (0xF, 0x15) => 10,
+            (0xF, 0x18) => 10,
+            (0xF, 0x1E) => 16,
+            (0xF, 0x29) => 20,
+            // The digits are found by repeated subtractions
+            (0xF, 0x33) => 84 + 16 * (vx / 100 + vx / 10 % 10 + vx % 10) as u32,
+            (0xF, 0x55) | (0xF, 0x65) => 14 + 14 * (inst.x as u32 + 1),
+            _ => 10,
+        };
+        VIP_FETCH_CYCLES + routine
+    }
+
+    /**
+     * @brief Cost of DXYN on the COSMAC VIP.
+     *
+     * Each row of the sprite is shifted to the pixel column, which
+     * takes longer when the sprite does not start on a byte of the
+     * screen, as each row then spans two bytes.
+     * DXY0 draws 16 rows of 16 pixels, each row costing as much
+     * as two rows of 8 pixels.
+     * The wait for the display interrupt is not counted here, DXYN
+     * ends the frame.
+     */
+    fn vip_draw_cost(&self, inst: &ChipInst) -> u32 {
+        let vx = self.v[inst.x as usize] % self.disp.width();
+        let vy = self.v[inst.y as usize] % self.disp.height();
+        let (rows, row_bytes) = if inst.n == 0 {
+            (16, 2)
+        } else {
+            (inst.n as u32, 1)
+        };
+        let rows = rows.min(self.disp.height() as u32 - vy as u32);
+        let row_cost = if vx.is_multiple_of(8) { 42 } else { 70 };
+        26 + rows * row_bytes * row_cost
+    }
+
+    /**
+     * @brief Start a 60Hz frame.
+     *
+     * With the VIP timing, the frame gets the machine cycles left
+     * by the display interrupt. Cycles overspent by the last
+     * instruction of the previous frame are taken from this one,
+     * and the cycles left by a frame ended by a display wait are lost.
+     */
+    pub(super) fn begin_frame(&mut self) {
+        self.vblank_wait = false;
+        if self.config.vip_timing {
+            self.vip_budget = self.vip_budget.min(0) + VIP_FRAME_CYCLES - VIP_INTERRUPT_CYCLES;
+        }
+    }
+
+    /**
+     * @brief Tell if the current frame is over.
+     *
+     * The frame ends once `cycles` instructions were executed, or
+     * with the VIP timing once its machine cycles are spent.
+     * It also ends early if the chip exited, or waits for the display.
+     */
+    pub(super) fn frame_over(&self, executed: u32, cycles: u32) -> bool {
+        let spent = if self.config.vip_timing {
+            self.vip_budget <= 0
+        } else {
+            executed >= cycles
+        };
+        spent || self.exit || self.vblank_wait
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::{ChipInst, HeadlessChip8};
+
+    #[test]
+    fn draw_costs() {
+        let mut chip = HeadlessChip8::new_headless();
+        chip.v[1] = 8;
+        chip.v[2] = 3;
+        let cost = |chip: &HeadlessChip8, w| chip.vip_cost(&ChipInst::new(w));
+        assert_eq!(cost(&chip, 0xD125), 40 + 26 + 5 * 42);
+        assert_eq!(cost(&chip, 0xD225), 40 + 26 + 5 * 70);
+        assert_eq!(cost(&chip, 0xD120), 40 + 26 + 16 * 2 * 42);
+
+        // Rows below the screen are not drawn
+        chip.v[2] = 30;
+        assert_eq!(cost(&chip, 0xD125), 40 + 26 + 2 * 42);
+        assert_eq!(cost(&chip, 0xD120), 40 + 26 + 2 * 2 * 42);
+    }
+}
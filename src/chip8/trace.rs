@@ -0,0 +1,102 @@
+use super::{Audio, Chip8, Chip8Error, ChipInst, Input, Renderer};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+
+/**
+ * @brief Which instructions are traced.
+ *
+ * Classes are the first hexadecimal digit of the opcodes,
+ * such as 0xD for the drawing instructions.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    pub range: Option<RangeInclusive<u16>>, // Addresses traced, all if unset
+    pub classes: Vec<u8>,                   // Opcode classes traced, all if empty
+}
+
+impl TraceFilter {
+    pub fn matches(&self, addr: u16, inst: &ChipInst) -> bool {
+        self.range.as_ref().is_none_or(|r| r.contains(&addr))
+            && (self.classes.is_empty() || self.classes.contains(&inst.i))
+    }
+}
+
+/**
+ * @brief Write one line per executed instruction.
+ *
+ * Each line holds the address, the opcode, the mnemonic, the
+ * registers changed by the instruction, I and the timers:
+ * `0x204: 6108  LD V1, 0x08       V1=08 I=0x22a DT=0 ST=0`
+ */
+pub struct Tracer {
+    out: Box<dyn Write>,
+    pub filter: TraceFilter,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, filter: TraceFilter) -> Self {
+        Tracer { out, filter }
+    }
+
+    /**
+     * Trace to a file, or to the standard output if `path` is `-`.
+     */
+    pub fn open(path: &str, filter: TraceFilter) -> io::Result<Self> {
+        let out: Box<dyn Write> = if path == "-" {
+            Box::new(io::stdout())
+        } else {
+            Box::new(BufWriter::new(File::create(path)?))
+        };
+        Ok(Tracer::new(out, filter))
+    }
+}
+
+impl<R: Renderer, A: Audio, I: Input> Chip8<R, A, I> {
+    /**
+     * Start tracing the executed instructions, or stop with None.
+     */
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    /**
+     * @brief Write the trace line of an executed instruction.
+     *
+     * `v` holds the registers before the execution.
+     * If the line can not be written, tracing stops.
+     */
+    pub(super) fn trace(
+        &mut self,
+        addr: u16,
+        inst: &ChipInst,
+        v: &[u8; 16],
+    ) -> Result<(), Chip8Error> {
+        let tracer = match self.tracer.as_mut() {
+            Some(t) if t.filter.matches(addr, inst) => t,
+            _ => return Ok(()),
+        };
+
+        let deltas: Vec<String> = (0..16)
+            .filter(|&x| v[x] != self.v[x])
+            .map(|x| format!("V{:X}={:02x}", x, self.v[x]))
+            .collect();
+        let res = writeln!(
+            tracer.out,
+            "{:#05x}: {:04X}  {:<18} {}{}I={:#05x} DT={} ST={}",
+            addr,
+            inst.full,
+            inst.to_string(),
+            deltas.join(" "),
+            if deltas.is_empty() { "" } else { " " },
+            self.i,
+            self.dt,
+            self.st
+        );
+
+        res.map_err(|e| {
+            self.tracer = None;
+            Chip8Error::Io(e)
+        })
+    }
+}
@@ -0,0 +1,257 @@
+use chip8rs::chip8::input::keymap;
+use chip8rs::chip8::profile::Profile;
+use chip8rs::chip8::trace::TraceFilter;
+
+/**
+ * @brief Options given on the command line.
+ */
+pub struct Options {
+    pub program: String,            // Path of the program to run
+    pub headless: Option<u32>,      // If set, run this many frames without a window
+    pub cycles: u32,                // Instructions executed per frame
+    pub vip_timing: bool,           // If true, run as fast as the COSMAC VIP instead
+    pub profile: Option<Profile>,   // Platform whose quirks are emulated
+    pub display_wait: Option<bool>, // If set, overrides the display wait quirk of the platform
+    pub gdb: Option<u16>,           // If set, wait for a GDB client on this port
+    pub trace: Option<String>,      // If set, file where executed instructions are logged
+    pub trace_filter: TraceFilter,  // Instructions which are logged
+    pub seed: Option<u64>,          // If set, seed of the random numbers
+    pub record: Option<String>,     // If set, file where the inputs are recorded
+    pub replay: Option<String>,     // If set, movie whose inputs are played
+    pub keymap: Option<String>,     // If set, file of the keyboard bindings
+    pub bindings: Vec<String>,      // Bindings given on the command line, applied last
+}
+
+/**
+ * @brief What the program was asked to do.
+ */
+pub enum Command {
+    Run(Options),         // Run a program
+    Debug(Options),       // Run a program in the terminal debugger
+    Disasm(String, bool), // Print the disassembly of a program, following its control flow if set
+    Asm(String, String),  // Assemble a source file into a program
+}
+
+pub fn usage(prog: &str) -> String {
+    let profiles: Vec<&str> = Profile::ALL.iter().map(|p| p.name()).collect();
+    format!(
+        "Usage: {0} [options] <program-path>\n       \
+                {0} debug [options] <program-path>\n       \
+                {0} disasm [--flow] <program-path>\n       \
+                {0} asm <source-path> [-o <program-path>]\n\
+         Options:\n  \
+           --headless <frames>  Run without a window, then print the screen\n  \
+           --cycles <n>         Execute n instructions per frame (default {2})\n  \
+           --vip-timing         Give each instruction its COSMAC VIP duration\n  \
+           --profile <name>     Emulate the quirks of a platform ({1})\n  \
+           --display-wait <b>   Make DXYN wait for the next frame, 'on' or 'off'\n  \
+           --gdb <port>         Run without a window, driven by a GDB client\n  \
+           --trace <file>       Log the executed instructions, '-' for stdout\n  \
+           --trace-range <a-b>  Only log the instructions between these addresses\n  \
+           --trace-ops <x,..>   Only log the opcodes starting with these digits\n  \
+           --seed <n>           Seed the random numbers, to make runs reproducible\n  \
+           --record <file>      Record the keypad of each frame in a movie\n  \
+           --replay <file>      Play the keypad of a recorded movie\n  \
+           --keymap <file>      Read the keyboard bindings from a file\n  \
+           --key <x>=<name>     Bind the keypad key x to a keyboard key",
+        prog,
+        profiles.join(", "),
+        crate::OPS_PER_FRAME
+    )
+}
+
+/**
+ * Fetch the value following an option.
+ */
+fn value<'a>(it: &mut impl Iterator<Item = &'a String>, opt: &str) -> Result<&'a String, String> {
+    it.next().ok_or(format!("Missing value for '{}'", opt))
+}
+
+/**
+ * Parse a hexadecimal number, with or without 0x.
+ */
+fn parse_hex(s: &str) -> Result<u16, String> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    u16::from_str_radix(digits, 16).map_err(|e| format!("Invalid address '{}': {}", s, e))
+}
+
+/**
+ * Parse an address range, such as `200-2ff`.
+ */
+fn parse_range(s: &str) -> Result<std::ops::RangeInclusive<u16>, String> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or(format!("Invalid range '{}', expected <start>-<end>", s))?;
+    Ok(parse_hex(start)?..=parse_hex(end)?)
+}
+
+/**
+ * Parse opcode classes, such as `1,2,d`.
+ */
+fn parse_classes(s: &str) -> Result<Vec<u8>, String> {
+    s.split(',')
+        .map(|c| match u8::from_str_radix(c.trim(), 16) {
+            Ok(d) if d < 0x10 => Ok(d),
+            _ => Err(format!("Invalid opcode class '{}', expected 0-f", c)),
+        })
+        .collect()
+}
+
+/**
+ * @brief Parse the arguments, without the program name.
+ */
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    match args.first().map(|a| a.as_str()) {
+        Some("disasm") => parse_disasm(&args[1..]),
+        Some("asm") => parse_asm(&args[1..]),
+        Some("debug") => {
+            let opts = parse_run(&args[1..])?;
+            if opts.headless.is_some() || opts.gdb.is_some() {
+                return Err("The debugger can not run headless".to_string());
+            }
+            if opts.record.is_some() || opts.replay.is_some() {
+                return Err("The debugger can not record or replay movies".to_string());
+            }
+            Ok(Command::Debug(opts))
+        }
+        _ => Ok(Command::Run(parse_run(args)?)),
+    }
+}
+
+fn parse_disasm(args: &[String]) -> Result<Command, String> {
+    match args {
+        [program] => Ok(Command::Disasm(program.clone(), false)),
+        [flag, program] if flag == "--flow" => Ok(Command::Disasm(program.clone(), true)),
+        [] => Err("Missing program path".to_string()),
+        _ => Err(format!("Unexpected argument '{}'", args[args.len() - 1])),
+    }
+}
+
+fn parse_asm(args: &[String]) -> Result<Command, String> {
+    let mut source = None;
+    let mut output = None;
+
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "-o" => output = Some(value(&mut it, arg)?.clone()),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option '{}'", arg)),
+            _ if source.is_none() => source = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument '{}'", arg)),
+        }
+    }
+
+    let source: String = source.ok_or("Missing source path")?;
+    // By default, write the program next to its source
+    let output = output.unwrap_or_else(|| {
+        std::path::Path::new(&source)
+            .with_extension("ch8")
+            .to_string_lossy()
+            .into_owned()
+    });
+    Ok(Command::Asm(source, output))
+}
+
+fn parse_run(args: &[String]) -> Result<Options, String> {
+    let mut program = None;
+    let mut headless = None;
+    let mut cycles = crate::OPS_PER_FRAME;
+    let mut vip_timing = false;
+    let mut profile = None;
+    let mut display_wait = None;
+    let mut gdb = None;
+    let mut trace = None;
+    let mut trace_filter = TraceFilter::default();
+    let mut seed = None;
+    let mut record = None;
+    let mut replay = None;
+    let mut keymap = None;
+    let mut bindings = Vec::new();
+
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--headless" => {
+                let v = value(&mut it, arg)?;
+                let frames = v
+                    .parse::<u32>()
+                    .map_err(|e| format!("Invalid frame count '{}': {}", v, e))?;
+                headless = Some(frames);
+            }
+            "--cycles" => {
+                let v = value(&mut it, arg)?;
+                cycles = match v.parse::<u32>() {
+                    Ok(n) if n > 0 && n <= crate::MAX_CYCLES => n,
+                    _ => {
+                        return Err(format!(
+                            "Invalid cycle count '{}', expected 1 to {}",
+                            v,
+                            crate::MAX_CYCLES
+                        ))
+                    }
+                };
+            }
+            "--vip-timing" => vip_timing = true,
+            "--display-wait" => {
+                display_wait = match value(&mut it, arg)?.as_str() {
+                    "on" => Some(true),
+                    "off" => Some(false),
+                    v => return Err(format!("Invalid display wait '{}', expected on or off", v)),
+                };
+            }
+            "--profile" => profile = Some(value(&mut it, arg)?.parse::<Profile>()?),
+            "--gdb" => {
+                let v = value(&mut it, arg)?;
+                let port = v
+                    .parse::<u16>()
+                    .map_err(|e| format!("Invalid port '{}': {}", v, e))?;
+                gdb = Some(port);
+            }
+            "--trace" => trace = Some(value(&mut it, arg)?.clone()),
+            "--trace-range" => trace_filter.range = Some(parse_range(value(&mut it, arg)?)?),
+            "--trace-ops" => trace_filter.classes = parse_classes(value(&mut it, arg)?)?,
+            "--seed" => {
+                let v = value(&mut it, arg)?;
+                let n = v
+                    .parse::<u64>()
+                    .map_err(|e| format!("Invalid seed '{}': {}", v, e))?;
+                seed = Some(n);
+            }
+            "--record" => record = Some(value(&mut it, arg)?.clone()),
+            "--replay" => replay = Some(value(&mut it, arg)?.clone()),
+            "--keymap" => keymap = Some(value(&mut it, arg)?.clone()),
+            "--key" => {
+                let v = value(&mut it, arg)?;
+                keymap::parse_binding(v)?;
+                bindings.push(v.clone());
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option '{}'", arg)),
+            _ if program.is_none() => program = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument '{}'", arg)),
+        }
+    }
+
+    if record.is_some() && replay.is_some() {
+        return Err("Can not record and replay a movie at once".to_string());
+    }
+    if gdb.is_some() && (record.is_some() || replay.is_some()) {
+        return Err("GDB clients can not record or replay movies".to_string());
+    }
+
+    Ok(Options {
+        program: program.ok_or("Missing program path")?,
+        headless,
+        cycles,
+        vip_timing,
+        profile,
+        display_wait,
+        gdb,
+        trace,
+        trace_filter,
+        seed,
+        record,
+        replay,
+        keymap,
+        bindings,
+    })
+}
@@ -1,15 +1,30 @@
-mod chip8;
-use chip8::Chip8;
+mod cli;
+mod tui;
+
+use chip8rs::chip8::audio::Audio;
+use chip8rs::chip8::input::keymap::{self, Keymap};
+use chip8rs::chip8::input::Input;
+use chip8rs::chip8::movie::{self, Movie};
+use chip8rs::chip8::renderer::Renderer;
+use chip8rs::chip8::rewind::Rewind;
+use chip8rs::chip8::scheduler::FrameScheduler;
+use chip8rs::chip8::trace::Tracer;
+use chip8rs::chip8::{asm, disasm, gdb};
+use chip8rs::chip8::{Chip8, HeadlessChip8, SDLChip8};
+use cli::{Command, Options};
 
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Scancode};
 use sdl2::{AudioSubsystem, EventPump, VideoSubsystem};
 
-use std::thread;
-use std::time;
+use std::net::TcpListener;
+use std::time::{Duration, Instant};
 
 const OPS_PER_SEC: u64 = 700;
-const TIMER_FREQ_UPDATE: f32 = 1. / 60.;
+const OPS_PER_FRAME: u32 = (OPS_PER_SEC / FrameScheduler::FRAME_RATE as u64) as u32;
+const STATE_SLOTS: u8 = 10;
+const REWIND_FRAMES: usize = 60 * 30;
+const MAX_CYCLES: u32 = 10_000;
 
 /**
  * @brief Initializes SDL with video and audio system.
@@ -22,53 +37,565 @@ fn init_sdl() -> Result<(VideoSubsystem, AudioSubsystem, EventPump), String> {
     Ok((video_ssys, audio_ssys, event_pump))
 }
 
+/**
+ * @brief Apply the options to a new chip, and load its program.
+ */
+fn prepare<R: Renderer, A: Audio, I: Input>(chip: &mut Chip8<R, A, I>, opts: &Options) {
+    if let Some(profile) = opts.profile {
+        chip.set_profile(profile);
+    }
+    if let Some(wait) = opts.display_wait {
+        chip.config_mut().display_wait = wait;
+    }
+    if let Some(seed) = opts.seed {
+        chip.set_seed(seed);
+    }
+    chip.config_mut().vip_timing = opts.vip_timing;
+    chip.load_default_font();
+
+    if let Err(e) = chip.load_file(&opts.program) {
+        eprintln!("{}", e);
+        std::process::exit(3);
+    }
+
+    if let Some(path) = &opts.trace {
+        match Tracer::open(path, opts.trace_filter.clone()) {
+            Ok(tracer) => chip.set_tracer(Some(tracer)),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                std::process::exit(3);
+            }
+        }
+    }
+}
+
+/**
+ * @brief Keypad of each frame, played from a movie or recorded into one.
+ */
+struct Session {
+    replay: Option<Movie>,           // Movie being played
+    record: Option<(Movie, String)>, // Movie being recorded, and its path
+    frame: usize,                    // Frames played or recorded so far
+}
+
+impl Session {
+    /**
+     * @brief Open the movie to replay, or start the one to record.
+     *
+     * A replay runs with the seed, the platform, the quirks and
+     * the speed of its movie.
+     * A recording needs a seed, one is drawn if none was given.
+     */
+    fn new(opts: &mut Options) -> Result<Self, String> {
+        let mut session = Session {
+            replay: None,
+            record: None,
+            frame: 0,
+        };
+        if opts.replay.is_none() && opts.record.is_none() {
+            return Ok(session);
+        }
+
+        let rom = std::fs::read(&opts.program).map_err(|e| format!("{}: {}", opts.program, e))?;
+        let hash = movie::rom_hash(&rom);
+
+        if let Some(path) = &opts.replay {
+            let replay = Movie::open(path).map_err(|e| format!("{}: {}", path, e))?;
+            if replay.rom_hash != hash {
+                return Err(format!("{}: recorded with another program", path));
+            }
+            opts.cycles = replay.cycles;
+            opts.vip_timing = replay.vip_timing;
+            opts.display_wait = replay.display_wait;
+            opts.seed = Some(replay.seed);
+            opts.profile = replay.profile;
+            session.replay = Some(replay);
+        }
+
+        if let Some(path) = &opts.record {
+            let seed = *opts.seed.get_or_insert_with(rand::random);
+            let mut record = Movie::new(seed, hash, opts.profile, opts.cycles);
+            record.vip_timing = opts.vip_timing;
+            record.display_wait = opts.display_wait;
+            session.record = Some((record, path.clone()));
+        }
+        Ok(session)
+    }
+
+    /**
+     * True if the frames have to follow each other, without any
+     * state being loaded in between.
+     */
+    fn is_active(&self) -> bool {
+        self.replay.is_some() || self.record.is_some()
+    }
+
+    /**
+     * True while a movie plays, the keyboard being ignored.
+     */
+    fn is_replaying(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    /**
+     * @brief Set the keypad of the next frame.
+     *
+     * While a movie plays, it replaces the state of the keypad.
+     * While recording, the state of the keypad is appended to the movie.
+     */
+    fn next_frame<I: Input>(&mut self, input: &mut I) {
+        if let Some(replay) = &self.replay {
+            match replay.frame(self.frame) {
+                Some(keys) => input.set_keys(keys),
+                None => {
+                    println!("End of the replay, after {} frames", replay.len());
+                    input.set_keys(0);
+                    self.replay = None;
+                }
+            }
+        }
+        if let Some((record, _)) = &mut self.record {
+            record.record(input.keys());
+        }
+        self.frame += 1;
+    }
+
+    /**
+     * Write the recorded movie, if any.
+     */
+    fn finish(self) {
+        if let Some((record, path)) = self.record {
+            match record.save(&path) {
+                Ok(()) => println!("{} frames recorded in {}", record.len(), path),
+                Err(e) => eprintln!("{}: {}", path, e),
+            }
+        }
+    }
+}
+
+/**
+ * @brief Run a program for a number of frames without any window,
+ * then print the screen and the state of the chip.
+ */
+fn run_headless(opts: &Options, mut session: Session, frames: u32) {
+    let mut chip = HeadlessChip8::new_headless();
+    prepare(&mut chip, opts);
+
+    for _ in 0..frames {
+        if chip.has_exited() {
+            break;
+        }
+        session.next_frame(chip.input_mut());
+        if let Err(e) = chip.run_frame(opts.cycles) {
+            eprintln!("{}", e);
+        }
+    }
+    session.finish();
+
+    print!("{}", chip.framebuffer());
+    println!("{:?}", chip);
+}
+
+/**
+ * @brief Debug a program in the terminal.
+ */
+fn run_debug(opts: &Options) {
+    let mut chip = HeadlessChip8::new_headless();
+    prepare(&mut chip, opts);
+
+    if let Err(e) = tui::run(chip, opts.cycles) {
+        eprintln!("Terminal error: {}", e);
+        std::process::exit(2);
+    }
+}
+
+/**
+ * @brief Let a GDB client drive a program, without any window.
+ */
+fn run_gdb(opts: &Options, port: u16) {
+    let mut chip = HeadlessChip8::new_headless();
+    prepare(&mut chip, opts);
+
+    let res = TcpListener::bind(("127.0.0.1", port)).and_then(|listener| {
+        println!("Waiting for a GDB client on 127.0.0.1:{}", port);
+        let (stream, addr) = listener.accept()?;
+        println!("Client connected from {}", addr);
+        gdb::serve(&mut chip, stream, opts.cycles)
+    });
+    if let Err(e) = res {
+        eprintln!("GDB stub error: {}", e);
+        std::process::exit(2);
+    }
+    println!("Client detached");
+}
+
+/**
+ * @brief Print the disassembly of a program.
+ */
+fn run_disasm(path: &str, flow: bool) {
+    match std::fs::read(path) {
+        Ok(rom) if flow => print!(
+            "{}",
+            disasm::listing(&disasm::disassemble_flow(&rom, 0x200))
+        ),
+        Ok(rom) => print!("{}", disasm::listing(&disasm::disassemble(&rom, 0x200))),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(3);
+        }
+    }
+}
+
+/**
+ * @brief Assemble a source file, and write the program.
+ */
+fn run_asm(source: &str, output: &str) {
+    let text = match std::fs::read_to_string(source) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("{}: {}", source, e);
+            std::process::exit(3);
+        }
+    };
+    let rom = match asm::assemble(&text) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("{}: {}", source, e);
+            std::process::exit(4);
+        }
+    };
+    if let Err(e) = std::fs::write(output, &rom) {
+        eprintln!("{}: {}", output, e);
+        std::process::exit(3);
+    }
+}
+
 fn main() {
     // Check arguments to get the program to load.
     let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: {} <program-path>", args[0]);
-        std::process::exit(1);
+    let cmd = match cli::parse(&args[1..]) {
+        Ok(cmd) => cmd,
+        Err(e) => {
+            eprintln!("{}\n{}", e, cli::usage(&args[0]));
+            std::process::exit(1);
+        }
+    };
+
+    match cmd {
+        Command::Disasm(path, flow) => run_disasm(&path, flow),
+        Command::Asm(source, output) => run_asm(&source, &output),
+        Command::Debug(opts) => run_debug(&opts),
+        Command::Run(mut opts) => {
+            let session = match Session::new(&mut opts) {
+                Ok(session) => session,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(3);
+                }
+            };
+            match (opts.gdb, opts.headless) {
+                (Some(port), _) => run_gdb(&opts, port),
+                (None, Some(frames)) => run_headless(&opts, session, frames),
+                (None, None) => run_sdl(&opts, session),
+            }
+        }
     }
+}
 
-    // Initialize SDL.
-    let sdl_res = init_sdl();
-    if let Err(e) = &sdl_res {
-        eprintln!("SDL loading error: {}", e);
-        std::process::exit(2);
+/**
+ * Path of the save state of a slot, next to the program.
+ */
+fn state_path(program: &str, slot: u8) -> String {
+    format!("{}.state{}", program, slot)
+}
+
+fn save_slot(chip: &SDLChip8, program: &str, slot: u8) {
+    let path = state_path(program, slot);
+    match std::fs::write(&path, chip.save_state()) {
+        Ok(()) => println!("State saved in slot {}", slot),
+        Err(e) => eprintln!("{}: {}", path, e),
+    }
+}
+
+fn load_slot(chip: &mut SDLChip8, program: &str, slot: u8) {
+    let path = state_path(program, slot);
+    let res = std::fs::read(&path)
+        .map_err(|e| e.into())
+        .and_then(|data| chip.load_state(&data));
+    match res {
+        Ok(()) => println!("State loaded from slot {}", slot),
+        Err(e) => eprintln!("{}: {}", path, e),
+    }
+}
+
+/**
+ * Default keymap file, in the configuration directory of the user.
+ */
+fn default_keymap_path() -> Option<std::path::PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(std::path::PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| std::path::Path::new(&h).join(".config")))?;
+    Some(config.join("chip8rs").join("keymap"))
+}
+
+/**
+ * @brief Build the keymap of a program.
+ *
+ * The bindings of the keymap file, given or default, are applied
+ * over the default layout, then the ones of the command line.
+ */
+fn load_keymap(opts: &Options) -> Result<Keymap, String> {
+    let mut map = Keymap::default();
+
+    let text = match (&opts.keymap, default_keymap_path()) {
+        (Some(path), _) => {
+            let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            Some((path.clone(), text))
+        }
+        (None, Some(path)) => std::fs::read_to_string(&path)
+            .ok()
+            .map(|text| (path.to_string_lossy().into_owned(), text)),
+        (None, None) => None,
+    };
+    if let Some((path, text)) = text {
+        map.apply(&text, &opts.program)
+            .map_err(|e| format!("{}: {}", path, e))?;
+    }
+
+    for binding in opts.bindings.iter() {
+        let (key, code) = keymap::parse_binding(binding)?;
+        map.bind(key, code);
+    }
+    Ok(map)
+}
+
+/**
+ * @brief Speed of the SDL frontend, shown in the window title.
+ */
+struct Speed {
+    cycles: u32,    // Instructions executed per frame, without the VIP timing
+    slowdown: u32,  // 1 at full speed, 2 or 4 in slow motion
+    turbo: bool,    // True while the frames are not paced
+    paused: bool,   // True while no frame runs
+    frames: u32,    // Frames run since the start of the measure
+    since: Instant, // Start of the measure
+    percent: u32,   // Measured speed, 100 being 60 frames per second
+}
+
+impl Speed {
+    fn new(cycles: u32) -> Self {
+        Speed {
+            cycles,
+            slowdown: 1,
+            turbo: false,
+            paused: false,
+            frames: 0,
+            since: Instant::now(),
+            percent: 100,
+        }
     }
-    let (video_subsys, audio_subsys, mut event_pump) = sdl_res.unwrap();
+
+    /**
+     * Change the instructions per frame by about an eighth,
+     * so that both slow and fast programs are quick to tune.
+     */
+    fn faster(&mut self) {
+        self.cycles = (self.cycles + (self.cycles / 8).max(1)).min(MAX_CYCLES);
+    }
+
+    fn slower(&mut self) {
+        self.cycles = self.cycles.saturating_sub((self.cycles / 8).max(1)).max(1);
+    }
+
+    /**
+     * Go from full speed to half speed, quarter speed, and back.
+     */
+    fn toggle_slow_motion(&mut self) {
+        self.slowdown = match self.slowdown {
+            1 => 2,
+            2 => 4,
+            _ => 1,
+        };
+    }
+
+    /**
+     * Pause or resume, starting a new measure.
+     */
+    fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.frames = 0;
+        self.since = Instant::now();
+    }
+
+    /**
+     * Count a frame, and return true when the measure is updated.
+     */
+    fn tick(&mut self) -> bool {
+        self.frames += 1;
+        let elapsed = self.since.elapsed();
+        if elapsed < Duration::from_secs(1) {
+            return false;
+        }
+        let rate = self.frames as f32 / elapsed.as_secs_f32();
+        self.percent = (100. * rate / FrameScheduler::FRAME_RATE as f32).round() as u32;
+        self.frames = 0;
+        self.since = Instant::now();
+        true
+    }
+
+    /**
+     * Title of the window, `vip` telling if the chip follows
+     * the COSMAC VIP timing instead of the cycles per frame.
+     */
+    fn title(&self, vip: bool) -> String {
+        let mode = match (self.paused, self.turbo, self.slowdown) {
+            (true, _, _) => " - paused".to_string(),
+            (false, true, _) => " - turbo".to_string(),
+            (false, false, 1) => String::new(),
+            (false, false, n) => format!(" - 1/{} speed", n),
+        };
+        let rate = if vip {
+            "VIP timing".to_string()
+        } else {
+            format!("{} cycles/frame", self.cycles)
+        };
+        format!("Chip8rs - {} - {}%{}", rate, self.percent, mode)
+    }
+}
+
+/**
+ * Show the speed in the window title.
+ */
+fn show_speed(chip: &mut SDLChip8, speed: &Speed) {
+    let title = speed.title(chip.config().vip_timing);
+    chip.renderer_mut().set_title(&title);
+}
+
+/**
+ * @brief Run a program in a SDL window.
+ *
+ * F5 saves the state in the current slot, F8 loads it back,
+ * F6 and F7 select the previous and next slots.
+ * Holding Backspace runs time backwards, one frame at a time.
+ * States can not be loaded while a movie is recorded or played.
+ * `+` and `-` change the instructions per frame, holding Tab
+ * runs as fast as possible, and F2 toggles the slow motion.
+ * P pauses and resumes, N runs a single frame while paused,
+ * and F12 restarts the program.
+ */
+fn run_sdl(opts: &Options, mut session: Session) {
+    // Initialize SDL.
+    let (video_subsys, audio_subsys, mut event_pump) = match init_sdl() {
+        Ok(sdl) => sdl,
+        Err(e) => {
+            eprintln!("SDL loading error: {}", e);
+            std::process::exit(2);
+        }
+    };
 
     // Create a window for SDL.
-    let window = video_subsys
+    let window = match video_subsys
         .window("Chip8rs", 800, 400)
         .position_centered()
         .build()
-        .unwrap();
+    {
+        Ok(window) => window,
+        Err(e) => {
+            eprintln!("SDL window error: {}", e);
+            std::process::exit(2);
+        }
+    };
 
     // Create the Chip8 emulator.
-    let mut chip = Chip8::new_sdl(window, &audio_subsys).unwrap();
-    chip.load_default_font();
-
-    // Load the program given in arguments.
-    if let Err(e) = chip.load_file(&args[1]) {
-        eprintln!("{}", e);
-        std::process::exit(3);
+    let mut chip = match SDLChip8::new_sdl(window, &audio_subsys) {
+        Ok(chip) => chip,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    prepare(&mut chip, opts);
+    match load_keymap(opts) {
+        Ok(map) => chip.input_mut().set_keymap(map),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(3);
+        }
     }
 
-    // Timer
-    let mut timer = time::Instant::now();
+    let mut slot: u8 = 0;
+    let mut rewind = Rewind::new(REWIND_FRAMES);
+    let mut scheduler = FrameScheduler::default();
+    let mut speed = Speed::new(opts.cycles);
+    show_speed(&mut chip, &speed);
 
-    // Main loop
+    // Main loop, one iteration per frame
     'running: loop {
+        // Set to run a single frame while paused
+        let mut advance = false;
+
         // Check events
 
         for event in event_pump.poll_iter() {
+            if !session.is_replaying() {
+                chip.input_mut().handle_event(&event);
+            }
             match event {
                 Event::Quit { .. }
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(key),
+                    repeat: false,
+                    ..
+                } => match key {
+                    Keycode::F5 => save_slot(&chip, &opts.program, slot),
+                    Keycode::F8 if session.is_active() => {
+                        eprintln!("States can not be loaded during a movie")
+                    }
+                    Keycode::F8 => load_slot(&mut chip, &opts.program, slot),
+                    Keycode::F12 if session.is_active() => {
+                        eprintln!("The program can not be restarted during a movie")
+                    }
+                    Keycode::F12 => match chip.reset() {
+                        Ok(()) => println!("Program restarted"),
+                        Err(e) => eprintln!("{}", e),
+                    },
+                    Keycode::P => {
+                        speed.toggle_pause();
+                        show_speed(&mut chip, &speed);
+                    }
+                    Keycode::N if speed.paused => advance = true,
+                    Keycode::F6 | Keycode::F7 => {
+                        slot = if key == Keycode::F6 {
+                            (slot + STATE_SLOTS - 1) % STATE_SLOTS
+                        } else {
+                            (slot + 1) % STATE_SLOTS
+                        };
+                        println!("Slot {} selected", slot);
+                    }
+                    Keycode::Equals | Keycode::KpPlus | Keycode::Minus | Keycode::KpMinus
+                        if session.is_active() || chip.config().vip_timing =>
+                    {
+                        eprintln!("The speed can not change during a movie or with the VIP timing")
+                    }
+                    Keycode::Equals | Keycode::KpPlus => {
+                        speed.faster();
+                        show_speed(&mut chip, &speed);
+                    }
+                    Keycode::Minus | Keycode::KpMinus => {
+                        speed.slower();
+                        show_speed(&mut chip, &speed);
+                    }
+                    Keycode::F2 => {
+                        speed.toggle_slow_motion();
+                        scheduler.set_rate(FrameScheduler::FRAME_RATE / speed.slowdown);
+                        show_speed(&mut chip, &speed);
+                    }
+                    _ => {}
+                },
                 _ => {}
             }
         }
@@ -77,22 +604,41 @@ fn main() {
             break 'running;
         }
 
-        // Fetch and execute the next instruction.
-        let inst = chip.fetch();
-        chip.execute(&inst, Some(&event_pump));
+        let keyboard = event_pump.keyboard_state();
+        let rewinding = !session.is_active() && keyboard.is_scancode_pressed(Scancode::Backspace);
+        let turbo = keyboard.is_scancode_pressed(Scancode::Tab);
+        if turbo != speed.turbo {
+            speed.turbo = turbo;
+            show_speed(&mut chip, &speed);
+            if !turbo {
+                scheduler.reset();
+            }
+        }
 
-        // Update timers
-        let now = time::Instant::now();
-        let diff = now - timer;
-        if diff.as_secs_f32() >= TIMER_FREQ_UPDATE {
-            chip.update_timers();
-            timer = now;
+        if rewinding {
+            // Go back to the previous frame
+            if let Some(state) = rewind.pop() {
+                if let Err(e) = chip.load_state(&state) {
+                    eprintln!("{}", e);
+                }
+            }
+        } else if !speed.paused || advance {
+            // Set the keypad of the movie, if any, then run the frame.
+            session.next_frame(chip.input_mut());
+            if let Err(e) = chip.run_frame(speed.cycles) {
+                eprintln!("{}", e);
+            }
+            rewind.push(chip.save_state());
         }
 
-        // Change buzzer sound if needed.
-        chip.refresh_buzzer();
+        if !speed.paused && speed.tick() {
+            show_speed(&mut chip, &speed);
+        }
 
-        // Simulate old computer performance.
-        thread::sleep(time::Duration::from_millis(1000 / OPS_PER_SEC));
+        // Wait for the next frame, unless running as fast as possible.
+        if !speed.turbo || speed.paused {
+            scheduler.wait();
+        }
     }
+    session.finish();
 }
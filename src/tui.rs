@@ -0,0 +1,321 @@
+use chip8rs::chip8::debugger::StopReason;
+use chip8rs::chip8::instruction::ChipInst;
+use chip8rs::chip8::HeadlessChip8;
+
+use crossterm::cursor::{self, MoveTo};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+const FRAME: Duration = Duration::from_micros(16_667);
+const DISASM_LINES: u16 = 20; // Instructions shown around the cursor
+const DISASM_WIDTH: usize = 40;
+const MEM_LINES: u16 = 8; // Lines of 16 bytes in the memory view
+const KEY_HOLD_FRAMES: u8 = 10; // Terminals do not report key releases
+
+const HELP: &str = "s step  n step over  o step out  r run  p pause  \
+                    t breakpoint  g run to cursor  up/down cursor  \
+                    pgup/pgdn memory  i memory at I  0-f keypad  q quit";
+
+/**
+ * @brief Keep the terminal in raw mode, and restore it when dropped.
+ */
+struct RawTerminal;
+
+impl RawTerminal {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, cursor::Hide)?;
+        Ok(RawTerminal)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), cursor::Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/**
+ * @brief State of the terminal debugger.
+ */
+struct Tui {
+    chip: HeadlessChip8,
+    cursor: u16,    // Address selected in the disassembly
+    mem_view: u16,  // First address of the memory view
+    status: String, // Last stop reason, or what the chip is doing
+    held: [u8; 16], // Frames left before releasing each key
+    quit: bool,
+}
+
+/**
+ * @brief Debug a program in the terminal.
+ *
+ * The chip starts paused on its first instruction, and runs
+ * `cycles` instructions per frame once resumed.
+ */
+pub fn run(chip: HeadlessChip8, cycles: u32) -> io::Result<()> {
+    let _term = RawTerminal::enter()?;
+    let mut out = io::stdout();
+    let mut tui = Tui {
+        cursor: chip.pc(),
+        mem_view: chip.index(),
+        chip,
+        status: "Paused".to_string(),
+        held: [0; 16],
+        quit: false,
+    };
+
+    queue!(out, Clear(ClearType::All))?;
+    let mut next = Instant::now();
+    while !tui.quit {
+        tui.draw(&mut out)?;
+
+        // Handle the keys until the next frame
+        next += FRAME;
+        while let Some(timeout) = next.checked_duration_since(Instant::now()) {
+            if !event::poll(timeout)? {
+                break;
+            }
+            match event::read()? {
+                Event::Key(key) if key.kind != KeyEventKind::Release => tui.key(key),
+                Event::Resize(..) => queue!(out, Clear(ClearType::All))?,
+                _ => {}
+            }
+        }
+        // Do not try to catch up if the terminal is too slow
+        next = next.max(Instant::now() - FRAME);
+
+        tui.frame(cycles);
+    }
+    Ok(())
+}
+
+impl Tui {
+    fn frame(&mut self, cycles: u32) {
+        if let Some(stop) = self.chip.debug_frame(cycles) {
+            self.stopped(stop);
+        } else if self.chip.debugger().is_running() {
+            // Follow the program while it runs
+            self.cursor = self.chip.pc();
+        }
+
+        for (key, held) in self.held.iter_mut().enumerate() {
+            if *held > 0 {
+                *held -= 1;
+                if *held == 0 {
+                    self.chip.input_mut().release(key as u8);
+                }
+            }
+        }
+    }
+
+    fn stopped(&mut self, stop: StopReason) {
+        self.status = stop.to_string();
+        self.cursor = self.chip.pc();
+    }
+
+    fn key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Char('s') => {
+                self.chip.debugger_mut().step(1);
+                self.status = "Stepping".to_string();
+            }
+            KeyCode::Char('n') => {
+                self.chip.step_over();
+                self.status = "Stepping over".to_string();
+            }
+            KeyCode::Char('o') => {
+                self.status = if self.chip.step_out() {
+                    "Stepping out".to_string()
+                } else {
+                    "Not in a subroutine".to_string()
+                };
+            }
+            KeyCode::Char('r') => {
+                self.chip.debugger_mut().resume();
+                self.status = "Running".to_string();
+            }
+            KeyCode::Char('g') => {
+                self.chip.debugger_mut().run_to(self.cursor);
+                self.status = "Running to cursor".to_string();
+            }
+            KeyCode::Char('p') => {
+                self.chip.debugger_mut().pause();
+                self.status = "Paused".to_string();
+                self.cursor = self.chip.pc();
+            }
+            KeyCode::Char('t') => {
+                let debugger = self.chip.debugger_mut();
+                if !debugger.remove_breakpoint(self.cursor) {
+                    debugger.add_breakpoint(self.cursor, None);
+                }
+            }
+            KeyCode::Char('i') => self.mem_view = self.chip.index(),
+            KeyCode::Up => self.cursor = self.cursor.saturating_sub(2),
+            KeyCode::Down => self.cursor = self.cursor.saturating_add(2),
+            KeyCode::PageUp => self.mem_view = self.mem_view.saturating_sub(16 * MEM_LINES),
+            KeyCode::PageDown => self.mem_view = self.mem_view.saturating_add(16 * MEM_LINES),
+            KeyCode::Char(c) if c.is_ascii_hexdigit() => {
+                let k = c.to_digit(16).unwrap_or(0) as u8;
+                self.chip.input_mut().press(k);
+                self.held[k as usize] = KEY_HOLD_FRAMES;
+            }
+            _ => {}
+        }
+    }
+
+    fn word_at(&self, addr: u16) -> Option<u16> {
+        let mem = self.chip.memory();
+        let a = addr as usize;
+        if a + 1 < mem.len() {
+            Some(u16::from_be_bytes([mem[a], mem[a + 1]]))
+        } else {
+            None
+        }
+    }
+
+    /**
+     * Lines shown on the right of the disassembly.
+     */
+    fn side_panel(&self) -> Vec<String> {
+        let chip = &self.chip;
+        let v = chip.registers();
+        let mut lines: Vec<String> = (0..4)
+            .map(|r| {
+                let regs: Vec<String> = (4 * r..4 * r + 4)
+                    .map(|x| format!("V{:X} {:02x}", x, v[x]))
+                    .collect();
+                regs.join("  ")
+            })
+            .collect();
+        lines.push(format!(
+            "I  {:#05x}  PC {:#05x}  SP {}",
+            chip.index(),
+            chip.pc(),
+            chip.sp()
+        ));
+        lines.push(format!(
+            "DT {:<5}  ST {}",
+            chip.delay_timer(),
+            chip.sound_timer()
+        ));
+        lines.push(String::new());
+        lines.push("Call stack:".to_string());
+        for ret in chip.call_stack().iter().rev() {
+            lines.push(format!("  {:#05x}", ret));
+        }
+        lines
+    }
+
+    fn draw(&self, out: &mut impl Write) -> io::Result<()> {
+        let pc = self.chip.pc();
+        let side = self.side_panel();
+
+        // Disassembly around the cursor, with the registers on its right
+        let start = self.cursor.saturating_sub(DISASM_LINES);
+        for row in 0..DISASM_LINES {
+            let addr = start.wrapping_add(2 * row);
+            let bp = if self.chip.debugger().has_breakpoint(addr) {
+                '*'
+            } else {
+                ' '
+            };
+            let here = if addr == pc { '>' } else { ' ' };
+            let text = match self.word_at(addr) {
+                Some(w) => format!(
+                    "{}{} {:#05x}: {:04X}  {}",
+                    bp,
+                    here,
+                    addr,
+                    w,
+                    ChipInst::new(w)
+                ),
+                None => String::new(),
+            };
+            let attr = if addr == self.cursor {
+                Attribute::Reverse
+            } else {
+                Attribute::Reset
+            };
+            queue!(
+                out,
+                MoveTo(0, row),
+                SetAttribute(attr),
+                Print(format!("{:<w$.w$}", text, w = DISASM_WIDTH)),
+                SetAttribute(Attribute::Reset),
+                Print("  "),
+                Print(side.get(row as usize).map_or("", |s| s.as_str())),
+                Clear(ClearType::UntilNewLine),
+            )?;
+        }
+
+        // Status line
+        let mut row = DISASM_LINES;
+        let state = if self.chip.debugger().is_running() {
+            "running"
+        } else {
+            "paused"
+        };
+        queue!(
+            out,
+            MoveTo(0, row),
+            Print(format!("[{}] {}", state, self.status)),
+            Clear(ClearType::UntilNewLine),
+        )?;
+        row += 1;
+
+        // The screen, with two pixel rows per character
+        let disp = self.chip.framebuffer();
+        for y in (0..disp.height()).step_by(2) {
+            let line: String = (0..disp.width())
+                .map(|x| match (disp.pixel(x, y), disp.pixel(x, y + 1)) {
+                    (false, false) => ' ',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (true, true) => '█',
+                })
+                .collect();
+            queue!(
+                out,
+                MoveTo(0, row),
+                Print(format!("|{}|", line)),
+                Clear(ClearType::UntilNewLine),
+            )?;
+            row += 1;
+        }
+
+        // Memory view
+        let mem = self.chip.memory();
+        for r in 0..MEM_LINES {
+            let addr = self.mem_view as usize + 16 * r as usize;
+            let bytes: Vec<String> = (addr..addr + 16)
+                .map(|a| {
+                    mem.get(a)
+                        .map_or("  ".to_string(), |b| format!("{:02x}", b))
+                })
+                .collect();
+            queue!(
+                out,
+                MoveTo(0, row),
+                Print(format!("{:#06x}: {}", addr, bytes.join(" "))),
+                Clear(ClearType::UntilNewLine),
+            )?;
+            row += 1;
+        }
+
+        queue!(
+            out,
+            MoveTo(0, row),
+            Print(HELP),
+            Clear(ClearType::FromCursorDown),
+        )?;
+        out.flush()
+    }
+}